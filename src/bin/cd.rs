@@ -1,21 +1,33 @@
 use tokio::select;
-use tracing::{info, Level, warn};
+use tracing::{error, info, Level, warn};
 use ex_rs::db;
 use ex_rs::service::check_diff;
 use time::{macros::format_description, UtcOffset};
 use tracing_subscriber::{fmt::time::OffsetTime, EnvFilter};
 use ex_rs::conf::config::Conf;
+use ex_rs::conf::layers::ValueSource;
+use ex_rs::conf::watch;
+use ex_rs::helpers::log_writer::ReloadableWriter;
+use ex_rs::helpers::snapshot;
+
+// how often config.toml is checked for changes
+const CONFIG_WATCH_SECS: u64 = 5;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let conf = Conf::get();
-    let log_path = env!("CARGO_MANIFEST_DIR").to_string() + &conf.log.path;
+    let conf = match Conf::init() {
+        Ok(conf) => conf,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let log_path = conf.log_dir();
     let log_name = &conf.log.name;
 
     // let file_appender = tracing_appender::rolling::hourly(log_path, log_name);
-    // 日志输出到文件
-    let file_appender = tracing_appender::rolling::never(log_path, log_name);
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    // 日志输出到文件, 配置变更时切换文件
+    let log_writer = ReloadableWriter::new(log_path, log_name);
 
     // 设置日志日期格式
     let local_time = OffsetTime::new(
@@ -24,7 +36,7 @@ async fn main() -> anyhow::Result<()> {
     );
 
     tracing_subscriber::fmt()
-        .with_writer(non_blocking)
+        .with_writer(log_writer.clone())
         // all spans/events with a level higher than TRACE (e.g, info, warn, etc.)
         // will be written to stdout.
         .with_env_filter(EnvFilter::from_default_env())
@@ -33,22 +45,36 @@ async fn main() -> anyhow::Result<()> {
         // sets this to be the default, global collector for this application.
         .init();
     warn!("check diff ...");
+    warn!("config loaded from {}", conf.config_path);
+    for (key, source) in conf.sources.iter().filter(|(_, s)| matches!(s, ValueSource::Env(_))) {
+        warn!("config {} overridden by {}", key, source);
+    }
+    let live_conf = watch::spawn(conf, tokio::time::Duration::from_secs(CONFIG_WATCH_SECS));
+    log_writer.follow(live_conf.clone());
 
     let (close_tx, mut close_rx) = tokio::sync::mpsc::unbounded_channel::<bool>();
     let wait_loop = tokio::spawn(async move {
-        'hello: loop {
-            select! {
-                _ = close_rx.recv() => break 'hello
-            }
-        }
+        close_rx.recv().await;
     });
 
     db::init_db().await?;
-    let c = check_diff::CheckDiff::new();
+    let sled_db = db::get_async_sled_db().unwrap();
+    let cache = db::get_async_coin_symbols_cache().unwrap();
+    // serve the last known state while REST catches up
+    match snapshot::restore(sled_db, cache) {
+        Ok(Some((age, restored))) => warn!("degraded start: restored {} entries from a snapshot {:?} old", restored, age),
+        Ok(None) => warn!("no coin symbol cache snapshot, cold start"),
+        Err(e) => error!("restore coin symbol cache err: {:?}", e),
+    }
+    if conf.sled.snapshot_secs > 0 {
+        snapshot::spawn(sled_db, cache, tokio::time::Duration::from_secs(conf.sled.snapshot_secs));
+    }
+    let c = check_diff::CheckDiff::new(live_conf);
     c.init_coin_symbols().await?;
     c.init_symbols().await?;
     c.last_price(close_tx.clone()).await?;
     c.book_ticker(close_tx.clone()).await?;
+    c.sweep_stale().await?;
 
     select! {
         _ = wait_loop => {
@@ -59,6 +85,11 @@ async fn main() -> anyhow::Result<()> {
             tokio::time::sleep(tokio::time::Duration::from_secs(2)).await;
         }
     }
+    match snapshot::save(sled_db, cache) {
+        Ok(saved) => info!("saved coin symbol cache snapshot, {} entries", saved.len()),
+        Err(e) => error!("snapshot coin symbol cache err: {:?}", e),
+    }
+    log_writer.shutdown();
 
     Ok(())
 }
\ No newline at end of file
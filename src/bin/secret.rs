@@ -0,0 +1,16 @@
+use std::io::Read;
+
+use ex_rs::conf::secret::{Secret, SECRET_PASSPHRASE_ENV};
+
+// 加密 api secret, 输出可写入 config.toml 的 { encrypted = "..." }
+// echo -n "$SECRET" | EX_RS_SECRET_PASSPHRASE=... cargo run --bin secret
+fn main() -> anyhow::Result<()> {
+    let passphrase = std::env::var(SECRET_PASSPHRASE_ENV)
+        .map_err(|_| anyhow::anyhow!("{} is not set", SECRET_PASSPHRASE_ENV))?;
+    let mut plain = String::new();
+    std::io::stdin().read_to_string(&mut plain)?;
+
+    let blob = Secret::encrypt(plain.trim_end_matches(['\r', '\n']), &passphrase)?;
+    println!("{{ encrypted = \"{}\" }}", blob);
+    Ok(())
+}
@@ -0,0 +1,72 @@
+use std::fmt;
+use std::path::PathBuf;
+
+/// A single missing or invalid config key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldError {
+    pub key: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new<K, M>(key: K, message: M) -> Self
+        where
+            K: Into<String>,
+            M: Into<String>,
+    {
+        Self {
+            key: key.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for FieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.key, self.message)
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The working directory could not be read while resolving the config path.
+    WorkingDir(std::io::Error),
+    /// The config file could not be opened or read.
+    Read { path: PathBuf, source: std::io::Error },
+    /// The file is not valid toml, or a key is missing or has the wrong type.
+    Parse { path: PathBuf, source: toml::de::Error },
+    /// The file parsed but some values are unusable. Every problem is listed.
+    Invalid { path: PathBuf, errors: Vec<FieldError> },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::WorkingDir(e) => write!(f, "read working directory err: {}", e),
+            ConfigError::Read { path, source } => {
+                write!(f, "read config file {} err: {}", path.display(), source)
+            }
+            ConfigError::Parse { path, source } => {
+                write!(f, "parse config file {} err: {}", path.display(), source)
+            }
+            ConfigError::Invalid { path, errors } => {
+                write!(f, "invalid config file {} ({} problems):", path.display(), errors.len())?;
+                for e in errors {
+                    write!(f, "\n  - {}", e)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::WorkingDir(e) => Some(e),
+            ConfigError::Read { source, .. } => Some(source),
+            ConfigError::Parse { source, .. } => Some(source),
+            ConfigError::Invalid { .. } => None,
+        }
+    }
+}
@@ -0,0 +1,297 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use toml::value::{Table, Value};
+
+use super::error::FieldError;
+
+// env overlay prefix, `EX_RS__MYSQL__URL` overrides `mysql.url`
+pub const ENV_PREFIX: &str = "EX_RS__";
+// separator between key segments in an env overlay name
+pub const ENV_SEPARATOR: &str = "__";
+
+/// Where a merged config value came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValueSource {
+    File(PathBuf),
+    Env(String),
+}
+
+impl fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueSource::File(path) => write!(f, "file {}", path.display()),
+            ValueSource::Env(name) => write!(f, "env {}", name),
+        }
+    }
+}
+
+/// A config document built up from several sources, later layers winning.
+/// Every leaf key (`mysql.url`, `ip_config[0].port`) remembers which layer set it.
+#[derive(Debug, Clone, Default)]
+pub struct Layers {
+    pub table: Table,
+    pub sources: BTreeMap<String, ValueSource>,
+}
+
+impl Layers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deep-merge a parsed file over the current layers. Tables merge key by key,
+    /// anything else (including arrays) replaces the previous value.
+    pub fn merge_file(&mut self, path: &Path, table: Table) {
+        let source = ValueSource::File(path.to_path_buf());
+        for (k, v) in table {
+            merge_value(&mut self.table, k.clone(), v, &k, &source, &mut self.sources);
+        }
+    }
+
+    /// Apply `EX_RS__SECTION__KEY=value` overlays. Segments are lowercased, numeric
+    /// segments index into arrays (`EX_RS__IP_CONFIG__0__PORT`), and an index one past
+    /// the end appends a new entry. Values take the type of the value they replace;
+    /// new keys are strings, or arrays when written as a toml array.
+    pub fn merge_env<I>(&mut self, vars: I) -> Result<(), Vec<FieldError>>
+        where
+            I: IntoIterator<Item=(String, String)>,
+    {
+        let mut errors = Vec::new();
+        let mut vars: Vec<_> = vars
+            .into_iter()
+            .filter(|(name, _)| name.starts_with(ENV_PREFIX))
+            .map(|(name, raw)| {
+                let segments: Vec<String> = name[ENV_PREFIX.len()..]
+                    .split(ENV_SEPARATOR)
+                    .map(|s| s.to_lowercase())
+                    .collect();
+                (segments, name, raw)
+            })
+            .collect();
+        // apply in a stable order so `__2__` entries exist before `__10__` ones
+        vars.sort_by(|a, b| cmp_segments(&a.0, &b.0).then_with(|| a.1.cmp(&b.1)));
+
+        for (segments, name, raw) in vars {
+            if segments.iter().any(|s| s.is_empty()) {
+                errors.push(FieldError::new(name, "empty key segment"));
+                continue;
+            }
+            match set_path(&mut self.table, &segments, &raw) {
+                Ok(key) => {
+                    self.sources.retain(|k, _| !is_under(k, &key));
+                    self.sources.insert(key, ValueSource::Env(name));
+                }
+                Err(e) => errors.push(FieldError::new(name, e)),
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    pub fn into_value(self) -> Value {
+        Value::Table(self.table)
+    }
+}
+
+fn merge_value(
+    table: &mut Table,
+    k: String,
+    v: Value,
+    key: &str,
+    source: &ValueSource,
+    sources: &mut BTreeMap<String, ValueSource>,
+) {
+    match (table.get_mut(&k), v) {
+        (Some(Value::Table(dst)), Value::Table(src)) => {
+            for (sk, sv) in src {
+                let child = format!("{}.{}", key, sk);
+                merge_value(dst, sk, sv, &child, source, sources);
+            }
+        }
+        (_, v) => {
+            sources.retain(|s, _| !is_under(s, key));
+            record_leaves(&v, key, source, sources);
+            table.insert(k, v);
+        }
+    }
+}
+
+fn record_leaves(v: &Value, key: &str, source: &ValueSource, sources: &mut BTreeMap<String, ValueSource>) {
+    match v {
+        Value::Table(t) => {
+            for (k, v) in t {
+                record_leaves(v, &format!("{}.{}", key, k), source, sources);
+            }
+        }
+        Value::Array(a) if a.iter().all(|v| v.is_table()) && !a.is_empty() => {
+            for (i, v) in a.iter().enumerate() {
+                record_leaves(v, &format!("{}[{}]", key, i), source, sources);
+            }
+        }
+        _ => {
+            sources.insert(key.to_string(), source.clone());
+        }
+    }
+}
+
+/// Order env overlay keys segment by segment, numeric segments as numbers.
+fn cmp_segments(a: &[String], b: &[String]) -> Ordering {
+    for (x, y) in a.iter().zip(b) {
+        let ord = match (is_index(x), is_index(y)) {
+            (true, true) => {
+                let (x, y) = (x.trim_start_matches('0'), y.trim_start_matches('0'));
+                x.len().cmp(&y.len()).then_with(|| x.cmp(y))
+            }
+            _ => x.cmp(y),
+        };
+        if ord != Ordering::Equal {
+            return ord;
+        }
+    }
+    a.len().cmp(&b.len())
+}
+
+fn is_index(seg: &str) -> bool {
+    !seg.is_empty() && seg.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Whether `key` is `parent` itself or nested below it.
+fn is_under(key: &str, parent: &str) -> bool {
+    key.strip_prefix(parent)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('.') || rest.starts_with('['))
+}
+
+/// Set the value at `segments`, creating intermediate tables. Returns the dotted key.
+fn set_path(table: &mut Table, segments: &[String], raw: &str) -> Result<String, String> {
+    let mut key = segments[0].clone();
+    let mut slot = table
+        .entry(segments[0].clone())
+        .or_insert_with(|| Value::Table(Table::new()));
+    let last = segments.len() - 1;
+
+    for (i, seg) in segments.iter().enumerate().skip(1) {
+        let is_leaf = i == last;
+        let is_index = is_index(seg);
+
+        if slot.is_table() && slot.as_table().is_some_and(|t| t.is_empty()) && is_index {
+            *slot = Value::Array(Vec::new());
+        }
+        slot = match slot {
+            Value::Table(t) => {
+                key = format!("{}.{}", key, seg);
+                if is_leaf && !t.contains_key(seg) {
+                    t.insert(seg.clone(), parse_scalar(None, raw)?);
+                    return Ok(key);
+                }
+                t.entry(seg.clone()).or_insert_with(|| Value::Table(Table::new()))
+            }
+            Value::Array(a) if is_index => {
+                let idx: usize = seg.parse().map_err(|_| format!("bad index {}", seg))?;
+                key = format!("{}[{}]", key, idx);
+                if idx == a.len() {
+                    if is_leaf {
+                        a.push(parse_scalar(None, raw)?);
+                        return Ok(key);
+                    }
+                    a.push(Value::Table(Table::new()));
+                } else if idx > a.len() {
+                    return Err(format!("index {} is past the end of {} entries", idx, a.len()));
+                }
+                &mut a[idx]
+            }
+            _ => return Err(format!("{} is not a table", key)),
+        };
+    }
+
+    if slot.is_table() && !slot.as_table().is_some_and(|t| t.is_empty()) {
+        return Err(format!("{} is a table, set one of its keys instead", key));
+    }
+    *slot = parse_scalar(Some(slot), raw)?;
+    Ok(key)
+}
+
+/// Read `raw` as the type of `current`, or guess the type for a new key.
+fn parse_scalar(current: Option<&Value>, raw: &str) -> Result<Value, String> {
+    let bad = |ty: &str| format!("{:?} is not a valid {}", raw, ty);
+    match current {
+        Some(Value::String(_)) => Ok(Value::String(raw.to_string())),
+        Some(Value::Integer(_)) => raw.parse().map(Value::Integer).map_err(|_| bad("integer")),
+        Some(Value::Float(_)) => raw.parse().map(Value::Float).map_err(|_| bad("float")),
+        Some(Value::Boolean(_)) => raw.parse().map(Value::Boolean).map_err(|_| bad("boolean")),
+        Some(Value::Array(_)) | Some(Value::Datetime(_)) => parse_toml(raw).ok_or_else(|| bad("toml value")),
+        // a new key's type is up to the field it lands in, see `config::lenient`
+        _ => Ok(parse_toml(raw)
+            .filter(|v| v.is_array())
+            .unwrap_or_else(|| Value::String(raw.to_string()))),
+    }
+}
+
+fn parse_toml(raw: &str) -> Option<Value> {
+    toml::from_str::<Table>(&format!("v = {}", raw))
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+    }
+
+    #[test]
+    fn test_env_overrides_file() {
+        let path = Path::new("/srv/config.toml");
+        let file: Table = toml::from_str(r#"
+            [mysql]
+            url = "mysql://file"
+            [redis]
+            url = "redis://file"
+            [[ip_config]]
+            name = "a"
+            port = "1080"
+        "#).unwrap();
+
+        let mut layers = Layers::new();
+        layers.merge_file(path, file);
+        layers.merge_env(env(&[
+            ("EX_RS__MYSQL__URL", "mysql://env"),
+            ("EX_RS__IP_CONFIG__0__PORT", "1081"),
+            ("EX_RS__IP_CONFIG__1__NAME", "b"),
+            ("EX_RS_CONFIG", "ignored.toml"),
+            ("PATH", "/bin"),
+        ])).unwrap();
+
+        let t = &layers.table;
+        assert_eq!(t["mysql"]["url"].as_str(), Some("mysql://env"));
+        assert_eq!(t["redis"]["url"].as_str(), Some("redis://file"));
+        assert_eq!(t["ip_config"][0]["port"].as_str(), Some("1081"));
+        assert_eq!(t["ip_config"][1]["name"].as_str(), Some("b"));
+
+        let file_src = ValueSource::File(path.to_path_buf());
+        assert_eq!(layers.sources["mysql.url"], ValueSource::Env("EX_RS__MYSQL__URL".into()));
+        assert_eq!(layers.sources["redis.url"], file_src);
+        assert_eq!(layers.sources["ip_config[0].name"], file_src);
+        assert_eq!(layers.sources["ip_config[0].port"], ValueSource::Env("EX_RS__IP_CONFIG__0__PORT".into()));
+        assert_eq!(layers.sources["ip_config[1].name"], ValueSource::Env("EX_RS__IP_CONFIG__1__NAME".into()));
+    }
+
+    #[test]
+    fn test_env_errors() {
+        let mut layers = Layers::new();
+        layers.merge_file(Path::new("c.toml"), toml::from_str("[[ip_config]]\nport = \"1\"\n[a]\nn = 1").unwrap());
+        let errors = layers.merge_env(env(&[
+            ("EX_RS__A__N", "x"),
+            ("EX_RS__IP_CONFIG__5__PORT", "2"),
+        ])).unwrap_err();
+        let keys: Vec<_> = errors.iter().map(|e| e.key.as_str()).collect();
+        assert_eq!(keys, vec!["EX_RS__A__N", "EX_RS__IP_CONFIG__5__PORT"]);
+    }
+}
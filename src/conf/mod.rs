@@ -1,2 +1,6 @@
 pub mod config;
+pub mod error;
+pub mod layers;
+pub mod secret;
 pub mod vars;
+pub mod watch;
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::num::NonZeroU32;
+use std::path::PathBuf;
+
+use anyhow::anyhow;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Deserializer};
+
+// env var holding the passphrase for `{ encrypted = "..." }` secrets
+pub const SECRET_PASSPHRASE_ENV: &str = "EX_RS_SECRET_PASSPHRASE";
+
+const SALT_LEN: usize = 16;
+const PBKDF2_ROUNDS: u32 = 100_000;
+
+/// A credential that never shows up in `Debug` or `Display` output.
+///
+/// In config it can be written as a plain string (discouraged), or as
+/// `{ file = "/run/secrets/binance" }`, `{ env = "BINANCE_SECRET" }` or
+/// `{ encrypted = "<hex>", passphrase_env = "..." }`. Encrypted blobs are made with
+/// `Secret::encrypt`; the passphrase env defaults to `EX_RS_SECRET_PASSPHRASE`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new<S>(value: S) -> Self
+        where
+            S: Into<String>,
+    {
+        Secret(value.into())
+    }
+
+    /// The plaintext. Keep the returned value out of logs.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.0.trim().is_empty()
+    }
+
+    /// Encrypt `plain` with AES-256-GCM under a PBKDF2 key derived from `passphrase`.
+    /// Returns hex of `salt | nonce | ciphertext+tag`.
+    pub fn encrypt(plain: &str, passphrase: &str) -> anyhow::Result<String> {
+        let rng = SystemRandom::new();
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rng.fill(&mut salt).map_err(|_| anyhow!("generate salt err"))?;
+        rng.fill(&mut nonce).map_err(|_| anyhow!("generate nonce err"))?;
+
+        let mut data = plain.as_bytes().to_vec();
+        derive_key(passphrase, &salt)?
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut data)
+            .map_err(|_| anyhow!("encrypt secret err"))?;
+
+        let mut blob = salt.to_vec();
+        blob.extend_from_slice(&nonce);
+        blob.extend_from_slice(&data);
+        Ok(hex::encode(blob))
+    }
+
+    pub fn decrypt(blob: &str, passphrase: &str) -> anyhow::Result<Secret> {
+        let blob = hex::decode(blob.trim()).map_err(|e| anyhow!("encrypted secret is not hex: {}", e))?;
+        if blob.len() < SALT_LEN + NONCE_LEN {
+            return Err(anyhow!("encrypted secret is too short"));
+        }
+        let (salt, rest) = blob.split_at(SALT_LEN);
+        let (nonce, data) = rest.split_at(NONCE_LEN);
+        let nonce = Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("bad nonce"))?;
+
+        let mut data = data.to_vec();
+        let plain = derive_key(passphrase, salt)?
+            .open_in_place(nonce, Aad::empty(), &mut data)
+            .map_err(|_| anyhow!("decrypt secret err: wrong passphrase or corrupt data"))?;
+        Ok(Secret(String::from_utf8(plain.to_vec())?))
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SecretSource {
+    Plain(String),
+    File { file: PathBuf },
+    Env { env: String },
+    Encrypted {
+        encrypted: String,
+        passphrase_env: Option<String>,
+    },
+}
+
+impl<'de> Deserialize<'de> for Secret {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+        where
+            D: Deserializer<'de>,
+    {
+        use serde::de::Error;
+
+        match SecretSource::deserialize(deserializer)? {
+            SecretSource::Plain(value) => Ok(Secret(value)),
+            SecretSource::File { file } => std::fs::read_to_string(&file)
+                .map(|v| Secret(v.trim_end_matches(['\r', '\n']).to_string()))
+                .map_err(|e| D::Error::custom(format!("read secret file {} err: {}", file.display(), e))),
+            SecretSource::Env { env } => std::env::var(&env)
+                .map(Secret)
+                .map_err(|_| D::Error::custom(format!("secret env {} is not set", env))),
+            SecretSource::Encrypted { encrypted, passphrase_env } => {
+                let passphrase_env = passphrase_env.unwrap_or_else(|| SECRET_PASSPHRASE_ENV.to_string());
+                let passphrase = std::env::var(&passphrase_env)
+                    .map_err(|_| D::Error::custom(format!("secret passphrase env {} is not set", passphrase_env)))?;
+                Secret::decrypt(&encrypted, &passphrase).map_err(D::Error::custom)
+            }
+        }
+    }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> anyhow::Result<LessSafeKey> {
+    let mut key = [0u8; 32];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ROUNDS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    let key = UnboundKey::new(&AES_256_GCM, &key).map_err(|_| anyhow!("build secret key err"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Deserialize)]
+    struct Holder {
+        key: Secret,
+    }
+
+    #[test]
+    fn test_secret_is_redacted() {
+        let s = Secret::new("hunter2");
+        assert_eq!(format!("{:?}", s), "Secret(***)");
+        assert_eq!(format!("{}", s), "***");
+        assert_eq!(s.expose(), "hunter2");
+    }
+
+    #[test]
+    fn test_encrypt_round_trip() {
+        let blob = Secret::encrypt("hunter2", "pass").unwrap();
+        assert_eq!(Secret::decrypt(&blob, "pass").unwrap().expose(), "hunter2");
+        assert!(Secret::decrypt(&blob, "wrong").is_err());
+    }
+
+    #[test]
+    fn test_deserialize_sources() {
+        let h: Holder = toml::from_str(r#"key = "plain""#).unwrap();
+        assert_eq!(h.key.expose(), "plain");
+
+        let path = std::env::temp_dir().join(format!("ex-rs-secret-{}", std::process::id()));
+        std::fs::write(&path, "from-file\n").unwrap();
+        let h: Holder = toml::from_str(&format!("key = {{ file = {:?} }}", path)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(h.key.expose(), "from-file");
+
+        std::env::set_var("EX_RS_TEST_SECRET_PASS", "pass");
+        let blob = Secret::encrypt("sealed", "pass").unwrap();
+        let h: Holder = toml::from_str(&format!(
+            r#"key = {{ encrypted = "{}", passphrase_env = "EX_RS_TEST_SECRET_PASS" }}"#,
+            blob
+        )).unwrap();
+        assert_eq!(h.key.expose(), "sealed");
+        assert!(!format!("{:?}", h).contains("sealed"));
+
+        let err = toml::from_str::<Holder>(r#"key = { env = "EX_RS_TEST_SECRET_UNSET" }"#).unwrap_err();
+        assert!(err.to_string().contains("EX_RS_TEST_SECRET_UNSET"), "{}", err);
+    }
+}
@@ -1,30 +1,327 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 // redis key
+#[deprecated(note = "use helpers::keys::price")]
 pub const REDIS_SPOT_PRICE_KEY: &str = "_spot_price";
+#[deprecated(note = "use helpers::keys::price")]
 pub const REDIS_FUTURES_PRICE_KEY: &str = "_futures_price";
+#[deprecated(note = "use helpers::keys::price")]
 pub const REDIS_DELIVERY_PRICE_KEY: &str = "_delivery_price";
 
 // platform
-pub const PLATFORM_BINANCE: &str = "binance";
-pub const PLATFORM_HUOBI: &str = "huobi";
-pub const PLATFORM_FTX: &str = "ftx";
+#[deprecated(note = "use Platform::Binance")]
+pub const PLATFORM_BINANCE: &str = Platform::Binance.as_str();
+#[deprecated(note = "use Platform::Huobi")]
+pub const PLATFORM_HUOBI: &str = Platform::Huobi.as_str();
+#[deprecated(note = "use Platform::Ftx")]
+pub const PLATFORM_FTX: &str = Platform::Ftx.as_str();
 
 // market
-pub const SPOT: &str = "spot";
-pub const FUTURES: &str = "futures";
-pub const DELIVERY: &str = "delivery";
+#[deprecated(note = "use Market::Spot")]
+pub const SPOT: &str = Market::Spot.as_str();
+#[deprecated(note = "use Market::Futures")]
+pub const FUTURES: &str = Market::Futures.as_str();
+#[deprecated(note = "use Market::Delivery")]
+pub const DELIVERY: &str = Market::Delivery.as_str();
 
 // trigger condition
-pub const TRIGGER_GREAT_EQUAL: &str = "ge";
-pub const TRIGGER_LESS_EQUAL: &str = "le";
+#[deprecated(note = "use TriggerCondition::GreaterEqual")]
+pub const TRIGGER_GREAT_EQUAL: &str = TriggerCondition::GreaterEqual.as_str();
+#[deprecated(note = "use TriggerCondition::LessEqual")]
+pub const TRIGGER_LESS_EQUAL: &str = TriggerCondition::LessEqual.as_str();
 
 // time in force
-pub const TIME_IN_FORECE_GTC: &str = "GTC";
-pub const TIME_IN_FORECE_IOC: &str = "IOC";
-pub const TIME_IN_FORECE_FOK: &str = "FOK";
+#[deprecated(note = "use TimeInForce::Gtc")]
+pub const TIME_IN_FORECE_GTC: &str = TimeInForce::Gtc.as_str();
+#[deprecated(note = "use TimeInForce::Ioc")]
+pub const TIME_IN_FORECE_IOC: &str = TimeInForce::Ioc.as_str();
+#[deprecated(note = "use TimeInForce::Fok")]
+pub const TIME_IN_FORECE_FOK: &str = TimeInForce::Fok.as_str();
 
 // order side
-pub const ORDER_SIDE_BUY: &str = "buy";
-pub const ORDER_SIDE_SELL: &str = "sell";
+#[deprecated(note = "use OrderSide::Buy")]
+pub const ORDER_SIDE_BUY: &str = OrderSide::Buy.as_str();
+#[deprecated(note = "use OrderSide::Sell")]
+pub const ORDER_SIDE_SELL: &str = OrderSide::Sell.as_str();
 
 // my ex prefix
 pub const EX_PREFIX: &str = "EX_";
+
+/// A string that doesn't name any variant of the enum it was parsed as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseEnumError {
+    pub kind: &'static str,
+    pub value: String,
+    pub expected: Vec<&'static str>,
+}
+
+impl fmt::Display for ParseEnumError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown {} {:?}, expected one of {}", self.kind, self.value, self.expected.join(", "))
+    }
+}
+
+impl std::error::Error for ParseEnumError {}
+
+/// Conversion to and from the spelling a given exchange uses on the wire.
+pub trait Wire: Sized {
+    fn to_wire(&self, platform: Platform) -> &'static str;
+
+    fn from_wire(platform: Platform, s: &str) -> Result<Self, ParseEnumError>;
+}
+
+/// Declares a closed set of names. The listed string is the canonical form used by
+/// `Display`, serde and config files; `FromStr` also accepts it in any case.
+macro_rules! str_enum {
+    ($(#[$meta:meta])* $name:ident, $kind:expr, { $($variant:ident => $s:expr),+ $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+        pub enum $name {
+            $($variant),+
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant),+];
+
+            pub const fn as_str(&self) -> &'static str {
+                match self {
+                    $($name::$variant => $s),+
+                }
+            }
+
+            fn parse_error(value: &str) -> ParseEnumError {
+                ParseEnumError {
+                    kind: $kind,
+                    value: value.to_string(),
+                    expected: Self::ALL.iter().map(|v| v.as_str()).collect(),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = ParseEnumError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Self::ALL
+                    .iter()
+                    .find(|v| v.as_str().eq_ignore_ascii_case(s))
+                    .copied()
+                    .ok_or_else(|| Self::parse_error(s))
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where
+                    S: Serializer,
+            {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+                where
+                    D: Deserializer<'de>,
+            {
+                let s = String::deserialize(deserializer)?;
+                s.parse().map_err(serde::de::Error::custom)
+            }
+        }
+    };
+}
+
+str_enum!(Platform, "platform", {
+    Binance => "binance",
+    Huobi => "huobi",
+    Ftx => "ftx",
+});
+
+str_enum!(
+    /// `Futures` is the linear (USDⓈ-M) market, `Delivery` the coin-margined one.
+    Market, "market", {
+    Spot => "spot",
+    Futures => "futures",
+    Delivery => "delivery",
+});
+
+str_enum!(OrderSide, "order side", {
+    Buy => "buy",
+    Sell => "sell",
+});
+
+str_enum!(TimeInForce, "time in force", {
+    Gtc => "GTC",
+    Ioc => "IOC",
+    Fok => "FOK",
+});
+
+str_enum!(TriggerCondition, "trigger condition", {
+    GreaterEqual => "ge",
+    LessEqual => "le",
+});
+
+impl OrderSide {
+    pub fn opposite(&self) -> OrderSide {
+        match self {
+            OrderSide::Buy => OrderSide::Sell,
+            OrderSide::Sell => OrderSide::Buy,
+        }
+    }
+}
+
+impl Wire for OrderSide {
+    fn to_wire(&self, platform: Platform) -> &'static str {
+        match (platform, self) {
+            (Platform::Binance, OrderSide::Buy) => "BUY",
+            (Platform::Binance, OrderSide::Sell) => "SELL",
+            (Platform::Huobi | Platform::Ftx, side) => side.as_str(),
+        }
+    }
+
+    fn from_wire(platform: Platform, s: &str) -> Result<Self, ParseEnumError> {
+        Self::ALL
+            .iter()
+            .find(|v| v.to_wire(platform) == s)
+            .copied()
+            .ok_or_else(|| Self::parse_error(s))
+    }
+}
+
+impl Wire for TimeInForce {
+    fn to_wire(&self, platform: Platform) -> &'static str {
+        match (platform, self) {
+            (Platform::Binance, tif) => tif.as_str(),
+            // huobi encodes it in order_price_type
+            (Platform::Huobi, TimeInForce::Gtc) => "limit",
+            (Platform::Huobi, TimeInForce::Ioc) => "ioc",
+            (Platform::Huobi, TimeInForce::Fok) => "fok",
+            (Platform::Ftx, TimeInForce::Gtc) => "gtc",
+            (Platform::Ftx, TimeInForce::Ioc) => "ioc",
+            (Platform::Ftx, TimeInForce::Fok) => "fok",
+        }
+    }
+
+    fn from_wire(platform: Platform, s: &str) -> Result<Self, ParseEnumError> {
+        Self::ALL
+            .iter()
+            .find(|v| v.to_wire(platform) == s)
+            .copied()
+            .ok_or_else(|| Self::parse_error(s))
+    }
+}
+
+impl Wire for TriggerCondition {
+    fn to_wire(&self, _platform: Platform) -> &'static str {
+        self.as_str()
+    }
+
+    fn from_wire(_platform: Platform, s: &str) -> Result<Self, ParseEnumError> {
+        Self::ALL
+            .iter()
+            .find(|v| v.as_str() == s)
+            .copied()
+            .ok_or_else(|| Self::parse_error(s))
+    }
+}
+
+impl From<OrderSide> for binance::rest_model::OrderSide {
+    fn from(side: OrderSide) -> Self {
+        match side {
+            OrderSide::Buy => binance::rest_model::OrderSide::Buy,
+            OrderSide::Sell => binance::rest_model::OrderSide::Sell,
+        }
+    }
+}
+
+impl From<binance::rest_model::OrderSide> for OrderSide {
+    fn from(side: binance::rest_model::OrderSide) -> Self {
+        match side {
+            binance::rest_model::OrderSide::Buy => OrderSide::Buy,
+            binance::rest_model::OrderSide::Sell => OrderSide::Sell,
+        }
+    }
+}
+
+impl From<TimeInForce> for binance::rest_model::TimeInForce {
+    fn from(tif: TimeInForce) -> Self {
+        match tif {
+            TimeInForce::Gtc => binance::rest_model::TimeInForce::GTC,
+            TimeInForce::Ioc => binance::rest_model::TimeInForce::IOC,
+            TimeInForce::Fok => binance::rest_model::TimeInForce::FOK,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T>(all: &[T])
+        where
+            T: Copy + PartialEq + fmt::Debug + fmt::Display + FromStr + Serialize + for<'de> Deserialize<'de>,
+            <T as FromStr>::Err: fmt::Debug,
+    {
+        for v in all {
+            assert_eq!(v.to_string().parse::<T>().unwrap(), *v);
+            assert_eq!(v.to_string().to_uppercase().parse::<T>().unwrap(), *v);
+            let json = serde_json::to_string(v).unwrap();
+            assert_eq!(json, format!("{:?}", v.to_string()));
+            assert_eq!(serde_json::from_str::<T>(&json).unwrap(), *v);
+        }
+    }
+
+    fn wire_round_trip<T>(all: &[T])
+        where
+            T: Copy + PartialEq + fmt::Debug + Wire,
+    {
+        for platform in Platform::ALL {
+            for v in all {
+                assert_eq!(T::from_wire(*platform, v.to_wire(*platform)).unwrap(), *v);
+            }
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        round_trip(Platform::ALL);
+        round_trip(Market::ALL);
+        round_trip(OrderSide::ALL);
+        round_trip(TimeInForce::ALL);
+        round_trip(TriggerCondition::ALL);
+
+        wire_round_trip(OrderSide::ALL);
+        wire_round_trip(TimeInForce::ALL);
+        wire_round_trip(TriggerCondition::ALL);
+    }
+
+    #[test]
+    #[allow(deprecated)]
+    fn test_deprecated_aliases() {
+        assert_eq!(PLATFORM_BINANCE.parse::<Platform>().unwrap(), Platform::Binance);
+        assert_eq!(DELIVERY.parse::<Market>().unwrap(), Market::Delivery);
+        assert_eq!(TRIGGER_LESS_EQUAL.parse::<TriggerCondition>().unwrap(), TriggerCondition::LessEqual);
+        assert_eq!(TIME_IN_FORECE_IOC.parse::<TimeInForce>().unwrap(), TimeInForce::Ioc);
+        assert_eq!(ORDER_SIDE_SELL.parse::<OrderSide>().unwrap(), OrderSide::Sell);
+    }
+
+    #[test]
+    fn test_wire_and_errors() {
+        assert_eq!(OrderSide::Buy.to_wire(Platform::Binance), "BUY");
+        assert_eq!(OrderSide::from_wire(Platform::Huobi, "sell").unwrap(), OrderSide::Sell);
+        assert!(OrderSide::from_wire(Platform::Binance, "buy").is_err());
+        assert_eq!(TimeInForce::Gtc.to_wire(Platform::Huobi), "limit");
+
+        let err = "kraken".parse::<Platform>().unwrap_err();
+        assert_eq!(err.to_string(), "unknown platform \"kraken\", expected one of binance, huobi, ftx");
+    }
+}
@@ -5,7 +5,7 @@ use redis::Client;
 use sled::Db;
 use sqlx::mysql::MySqlPoolOptions;
 use sqlx::MySqlPool;
-use crate::helpers::coin_symbol::CoinSymbolCache;
+use crate::helpers::coin_symbol::{CoinSymbolCache, MaxAge};
 
 static M_POOL: OnceCell<MySqlPool> = OnceCell::new();
 static R_REDIS: OnceCell<Client> = OnceCell::new();
@@ -25,10 +25,16 @@ pub async fn init_db() -> anyhow::Result<()> {
     let client = Client::open(c.redis.url.as_str())?;
     R_REDIS.set(client).unwrap();
 
-    let sled_db = sled::open(&c.sled.path.as_str())?;
+    let sled_db = sled::open(c.sled.path.as_str())?;
     SLED_DB.set(sled_db).unwrap();
 
-    COIN_SYMBOLS.set(CoinSymbolCache::new()).unwrap();
+    let max_age = MaxAge {
+        coin_symbols: c.strategy.coin_symbols_max_age(),
+        symbols: c.strategy.price_max_age(),
+        symbol_infos: c.strategy.symbol_info_max_age(),
+        book_tickers: c.strategy.book_ticker_max_age(),
+    };
+    COIN_SYMBOLS.set(CoinSymbolCache::with_max_age(max_age)).unwrap();
 
     Ok(())
 }
@@ -69,6 +75,7 @@ mod tests {
     use super::*;
 
     #[tokio::test]
+    #[ignore = "needs the MySQL and Redis from config.toml"]
     async fn test_database() {
         let _ = init_db().await;
         let pool = get_async_mysql_pool().unwrap();
@@ -44,6 +44,7 @@ mod tests {
     use super::*;
 
     #[tokio::test]
+    #[ignore = "needs the MySQL and Redis from config.toml"]
     async fn test_db() {
         let db = Db::new().await.unwrap();
         let row: (i64, String, String, String, Option<i64>, Option<i64>, Option<String>) = sqlx::query_as("SELECT * from p_config")
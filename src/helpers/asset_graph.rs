@@ -0,0 +1,311 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+
+use crate::conf::vars::OrderSide;
+use crate::helpers::instrument::InstrumentId;
+
+/// One step of a conversion: trade `id` to turn `from` into `to`. `Sell` when
+/// `from` is the instrument's base asset, `Buy` when it's the quote.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Hop {
+    pub id: InstrumentId,
+    pub from: String,
+    pub to: String,
+    pub side: OrderSide,
+}
+
+impl fmt::Display for Hop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}->{} {} {}", self.from, self.to, self.side, self.id)
+    }
+}
+
+/// State of one depth-first path search.
+struct Walk<'a> {
+    to: &'a str,
+    via: Option<&'a BTreeSet<String>>,
+    visited: BTreeSet<String>,
+    path: Vec<Hop>,
+    paths: &'a mut Vec<Vec<Hop>>,
+}
+
+/// Assets as nodes and tradable instruments as edges between their base and quote.
+#[derive(Debug, Clone, Default)]
+pub struct AssetGraph {
+    /// Instrument to its (base, quote).
+    markets: HashMap<InstrumentId, (String, String)>,
+    /// Asset to each neighbour and the instruments between the two, either way round.
+    adjacency: BTreeMap<String, BTreeMap<String, BTreeSet<InstrumentId>>>,
+    /// Quote asset to the instruments quoted in it.
+    by_quote: BTreeMap<String, BTreeSet<InstrumentId>>,
+}
+
+impl AssetGraph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an edge. Returns false when the instrument was already there with the
+    /// same assets.
+    pub fn insert<B, Q>(&mut self, id: InstrumentId, base: B, quote: Q) -> bool
+        where
+            B: Into<String>,
+            Q: Into<String>,
+    {
+        let (base, quote) = (base.into(), quote.into());
+        if self.markets.get(&id).is_some_and(|(b, q)| *b == base && *q == quote) {
+            return false;
+        }
+        self.remove(&id);
+        self.link(&base, &quote, &id);
+        self.link(&quote, &base, &id);
+        self.by_quote.entry(quote.clone()).or_default().insert(id.clone());
+        self.markets.insert(id, (base, quote));
+        true
+    }
+
+    /// Drop an edge, and any asset left without markets. Returns false when the
+    /// instrument wasn't there.
+    pub fn remove(&mut self, id: &InstrumentId) -> bool {
+        let (base, quote) = match self.markets.remove(id) {
+            Some(assets) => assets,
+            None => return false,
+        };
+        self.unlink(&base, &quote, id);
+        self.unlink(&quote, &base, id);
+        if let Some(ids) = self.by_quote.get_mut(&quote) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.by_quote.remove(&quote);
+            }
+        }
+        true
+    }
+
+    pub fn contains(&self, id: &InstrumentId) -> bool {
+        self.markets.contains_key(id)
+    }
+
+    /// Number of instruments.
+    pub fn len(&self) -> usize {
+        self.markets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.markets.is_empty()
+    }
+
+    /// Every asset with at least one market, sorted.
+    pub fn assets(&self) -> Vec<String> {
+        self.adjacency.keys().cloned().collect()
+    }
+
+    /// Instruments trading `from` straight into `to`, on every venue.
+    pub fn direct(&self, from: &str, to: &str) -> Vec<Hop> {
+        self.adjacency
+            .get(from)
+            .and_then(|neighbours| neighbours.get(to))
+            .map(|ids| ids.iter().map(|id| self.hop(id, from)).collect())
+            .unwrap_or_default()
+    }
+
+    /// Assets `base` is quoted in, sorted.
+    pub fn quotes_for(&self, base: &str) -> Vec<String> {
+        self.adjacency
+            .get(base)
+            .map(|neighbours| {
+                neighbours
+                    .iter()
+                    .filter(|(_, ids)| ids.iter().any(|id| self.markets[id].0 == base))
+                    .map(|(quote, _)| quote.clone())
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Instruments quoted in `quote`, e.g. every USDC market.
+    pub fn quoted_in(&self, quote: &str) -> BTreeSet<InstrumentId> {
+        self.by_quote.get(quote).cloned().unwrap_or_default()
+    }
+
+    /// Every way to turn `from` into `to` in at most `max_hops` trades, never visiting
+    /// an asset twice. Shortest first. The count grows quickly with `max_hops` around
+    /// hub assets, so keep it small.
+    pub fn paths(&self, from: &str, to: &str, max_hops: usize) -> Vec<Vec<Hop>> {
+        self.search(from, to, max_hops, None)
+    }
+
+    /// Like `paths`, but every asset between `from` and `to` must be one of `via`.
+    pub fn paths_via(&self, from: &str, to: &str, max_hops: usize, via: &BTreeSet<String>) -> Vec<Vec<Hop>> {
+        self.search(from, to, max_hops, Some(via))
+    }
+
+    fn search(&self, from: &str, to: &str, max_hops: usize, via: Option<&BTreeSet<String>>) -> Vec<Vec<Hop>> {
+        let mut paths = Vec::new();
+        if from == to || max_hops == 0 || !self.adjacency.contains_key(from) {
+            return paths;
+        }
+        let mut walk = Walk {
+            to,
+            via,
+            visited: BTreeSet::from([from.to_string()]),
+            path: Vec::new(),
+            paths: &mut paths,
+        };
+        self.walk(from, max_hops, &mut walk);
+        paths.sort_by_key(|p| p.len());
+        paths
+    }
+
+    fn walk(&self, at: &str, hops_left: usize, walk: &mut Walk<'_>) {
+        for (next, ids) in &self.adjacency[at] {
+            let arrived = next == walk.to;
+            if walk.visited.contains(next) {
+                continue;
+            }
+            // going through an asset needs a hop to spare, and it must be an allowed pivot
+            if !arrived && (hops_left == 1 || walk.via.is_some_and(|via| !via.contains(next))) {
+                continue;
+            }
+            for id in ids {
+                walk.path.push(self.hop(id, at));
+                if arrived {
+                    walk.paths.push(walk.path.clone());
+                } else {
+                    walk.visited.insert(next.clone());
+                    self.walk(next, hops_left - 1, walk);
+                    walk.visited.remove(next);
+                }
+                walk.path.pop();
+            }
+        }
+    }
+
+    fn hop(&self, id: &InstrumentId, from: &str) -> Hop {
+        let (base, quote) = &self.markets[id];
+        let (to, side) = if base == from {
+            (quote.clone(), OrderSide::Sell)
+        } else {
+            (base.clone(), OrderSide::Buy)
+        };
+        Hop {
+            id: id.clone(),
+            from: from.to_string(),
+            to,
+            side,
+        }
+    }
+
+    fn link(&mut self, from: &str, to: &str, id: &InstrumentId) {
+        self.adjacency
+            .entry(from.to_string())
+            .or_default()
+            .entry(to.to_string())
+            .or_default()
+            .insert(id.clone());
+    }
+
+    fn unlink(&mut self, from: &str, to: &str, id: &InstrumentId) {
+        if let Some(neighbours) = self.adjacency.get_mut(from) {
+            if let Some(ids) = neighbours.get_mut(to) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    neighbours.remove(to);
+                }
+            }
+            if neighbours.is_empty() {
+                self.adjacency.remove(from);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conf::vars::{Market, Platform};
+    use crate::helpers::instrument::Venue;
+
+    const SPOT: Venue = Venue::new(Platform::Binance, Market::Spot);
+    const HUOBI: Venue = Venue::new(Platform::Huobi, Market::Spot);
+
+    fn graph() -> AssetGraph {
+        let mut graph = AssetGraph::new();
+        for (symbol, base, quote) in [
+            ("BTCUSDT", "BTC", "USDT"),
+            ("ETHUSDT", "ETH", "USDT"),
+            ("ETHBTC", "ETH", "BTC"),
+            ("BTCUSDC", "BTC", "USDC"),
+            ("USDCUSDT", "USDC", "USDT"),
+            ("SOLUSDC", "SOL", "USDC"),
+        ] {
+            graph.insert(SPOT.instrument(symbol), base, quote);
+        }
+        graph
+    }
+
+    fn route(path: &[Hop]) -> String {
+        path.iter().map(|hop| hop.id.symbol.as_str()).collect::<Vec<_>>().join(",")
+    }
+
+    #[test]
+    fn test_direct_and_quotes() {
+        let mut graph = graph();
+        assert_eq!(graph.assets(), vec!["BTC", "ETH", "SOL", "USDC", "USDT"]);
+
+        let hops = graph.direct("BTC", "USDT");
+        assert_eq!(hops.len(), 1);
+        assert_eq!(hops[0].to_string(), "BTC->USDT sell binance:spot:BTCUSDT");
+        assert_eq!(graph.direct("USDT", "BTC")[0].side, OrderSide::Buy);
+        assert!(graph.direct("SOL", "BTC").is_empty());
+
+        // the same pair on a second exchange is a second edge
+        graph.insert(HUOBI.instrument("BTCUSDT"), "BTC", "USDT");
+        assert_eq!(graph.direct("BTC", "USDT").len(), 2);
+
+        assert_eq!(graph.quotes_for("BTC"), vec!["USDC", "USDT"]);
+        assert_eq!(graph.quotes_for("USDT"), Vec::<String>::new());
+        assert_eq!(graph.quotes_for("USDC"), vec!["USDT"]);
+        let usdc: Vec<_> = graph.quoted_in("USDC").into_iter().map(|id| id.symbol).collect();
+        assert_eq!(usdc, vec!["BTCUSDC", "SOLUSDC"]);
+    }
+
+    #[test]
+    fn test_paths() {
+        let graph = graph();
+        let paths: Vec<String> = graph.paths("SOL", "ETH", 3).iter().map(|p| route(p)).collect();
+        assert_eq!(paths, vec![
+            "SOLUSDC,BTCUSDC,ETHBTC",
+            "SOLUSDC,USDCUSDT,ETHUSDT",
+        ]);
+        assert!(graph.paths("SOL", "ETH", 2).is_empty());
+
+        let paths = graph.paths("BTC", "USDT", 2);
+        assert_eq!(route(&paths[0]), "BTCUSDT");
+        assert_eq!(paths.len(), 3);
+        let sides: Vec<OrderSide> = paths[1..].iter().flat_map(|p| p.iter().map(|hop| hop.side)).collect();
+        assert!(sides.contains(&OrderSide::Buy));
+
+        let via = BTreeSet::from(["USDT".to_string()]);
+        let paths: Vec<String> = graph.paths_via("BTC", "ETH", 3, &via).iter().map(|p| route(p)).collect();
+        assert_eq!(paths, vec!["ETHBTC", "BTCUSDT,ETHUSDT"]);
+
+        assert!(graph.paths("BTC", "BTC", 3).is_empty());
+        assert!(graph.paths("DOGE", "BTC", 3).is_empty());
+    }
+
+    #[test]
+    fn test_delist_removes_edges_and_orphaned_assets() {
+        let mut graph = graph();
+        assert!(!graph.insert(SPOT.instrument("SOLUSDC"), "SOL", "USDC"));
+        assert!(graph.remove(&SPOT.instrument("SOLUSDC")));
+        assert!(!graph.remove(&SPOT.instrument("SOLUSDC")));
+        assert!(!graph.assets().contains(&"SOL".to_string()));
+        assert_eq!(graph.quoted_in("USDC").len(), 1);
+
+        graph.remove(&SPOT.instrument("BTCUSDC"));
+        assert!(graph.quoted_in("USDC").is_empty());
+        assert_eq!(route(&graph.paths("USDC", "BTC", 2)[0]), "USDCUSDT,BTCUSDT");
+        assert_eq!(graph.len(), 4);
+    }
+}
@@ -0,0 +1,470 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use futures::StreamExt;
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::warn;
+
+/// A write queued by `cache::Pipeline`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteOp {
+    SetEx { key: String, value: Vec<u8>, seconds: usize },
+    HSet { key: String, fields: Vec<(String, Vec<u8>)> },
+    HDel { key: String, fields: Vec<String> },
+    Expire { key: String, seconds: usize },
+    Del { key: String },
+}
+
+impl WriteOp {
+    /// The key the write goes to.
+    pub fn key(&self) -> &str {
+        match self {
+            WriteOp::SetEx { key, .. }
+            | WriteOp::HSet { key, .. }
+            | WriteOp::HDel { key, .. }
+            | WriteOp::Expire { key, .. }
+            | WriteOp::Del { key } => key,
+        }
+    }
+}
+
+/// Byte-level storage behind `helpers::cache`. Implemented by a Redis connection
+/// and by `MemoryBackend`, so everything built on it can be tested without Redis.
+#[async_trait]
+pub trait CacheBackend: Send {
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// Store a value, without expiry when `ttl` is `None`.
+    async fn set_bytes(&mut self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> anyhow::Result<()>;
+
+    async fn set_ex_bytes(&mut self, key: &str, value: Vec<u8>, seconds: usize) -> anyhow::Result<()> {
+        self.set_bytes(key, value, Some(Duration::from_secs(seconds as u64))).await
+    }
+
+    /// Remaining time to live in milliseconds, `-1` without expiry and `-2` when the key is missing.
+    async fn pttl(&mut self, key: &str) -> anyhow::Result<i64>;
+
+    /// One value per key, in order.
+    async fn get_many_bytes(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>>;
+
+    async fn hgetall_bytes(&mut self, key: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>>;
+
+    /// Apply every write in one round trip, all or nothing.
+    async fn exec(&mut self, ops: Vec<WriteOp>) -> anyhow::Result<()>;
+
+    /// `SET key token NX PX millis`: true when the lock was taken.
+    async fn try_lock(&mut self, key: &str, token: &str, millis: usize) -> anyhow::Result<bool>;
+
+    /// Release a lock only if `token` still holds it. False when it expired or changed hands.
+    async fn unlock(&mut self, key: &str, token: &str) -> anyhow::Result<bool>;
+
+    /// Returns the number of subscribers that received the message.
+    async fn publish(&mut self, channel: &str, payload: Vec<u8>) -> anyhow::Result<usize>;
+}
+
+/// A message received on a subscribed channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub channel: String,
+    pub payload: Vec<u8>,
+}
+
+/// Pub/sub receiving side. Kept apart from `CacheBackend` because Redis needs a
+/// dedicated connection per subscription.
+#[async_trait]
+pub trait Subscriber: Sync {
+    /// Messages published to `channel` from now on. The subscription ends when the
+    /// receiver is dropped.
+    async fn subscribe(&self, channel: &str) -> anyhow::Result<UnboundedReceiver<Message>>;
+}
+
+// delete the lock only if we still own it
+const UNLOCK_SCRIPT: &str = r#"
+if redis.call("get", KEYS[1]) == ARGV[1] then
+    return redis.call("del", KEYS[1])
+else
+    return 0
+end
+"#;
+
+#[async_trait]
+impl CacheBackend for MultiplexedConnection {
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self.get(key).await?)
+    }
+
+    async fn set_bytes(&mut self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> anyhow::Result<()> {
+        match ttl {
+            Some(ttl) => self.pset_ex::<_, _, ()>(key, value, ttl.as_millis() as usize).await?,
+            None => self.set::<_, _, ()>(key, value).await?,
+        }
+        Ok(())
+    }
+
+    async fn pttl(&mut self, key: &str) -> anyhow::Result<i64> {
+        Ok(AsyncCommands::pttl(self, key).await?)
+    }
+
+    async fn get_many_bytes(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        Ok(redis::cmd("MGET").arg(keys).query_async(self).await?)
+    }
+
+    async fn hgetall_bytes(&mut self, key: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        let fields: HashMap<String, Vec<u8>> = self.hgetall(key).await?;
+        Ok(fields.into_iter().collect())
+    }
+
+    async fn exec(&mut self, ops: Vec<WriteOp>) -> anyhow::Result<()> {
+        if ops.is_empty() {
+            return Ok(());
+        }
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for op in ops {
+            match op {
+                WriteOp::SetEx { key, value, seconds } => pipe.set_ex(key, value, seconds).ignore(),
+                WriteOp::HSet { fields, .. } if fields.is_empty() => continue,
+                WriteOp::HSet { key, fields } => pipe.hset_multiple(key, &fields).ignore(),
+                WriteOp::HDel { fields, .. } if fields.is_empty() => continue,
+                WriteOp::HDel { key, fields } => pipe.hdel(key, fields).ignore(),
+                WriteOp::Expire { key, seconds } => pipe.expire(key, seconds).ignore(),
+                WriteOp::Del { key } => pipe.del(key).ignore(),
+            };
+        }
+        pipe.query_async::<_, ()>(self).await?;
+        Ok(())
+    }
+
+    async fn try_lock(&mut self, key: &str, token: &str, millis: usize) -> anyhow::Result<bool> {
+        let reply: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(token)
+            .arg("NX")
+            .arg("PX")
+            .arg(millis)
+            .query_async(self)
+            .await?;
+        Ok(reply.is_some())
+    }
+
+    async fn unlock(&mut self, key: &str, token: &str) -> anyhow::Result<bool> {
+        let deleted: i64 = redis::Script::new(UNLOCK_SCRIPT)
+            .key(key)
+            .arg(token)
+            .invoke_async(self)
+            .await?;
+        Ok(deleted == 1)
+    }
+
+    async fn publish(&mut self, channel: &str, payload: Vec<u8>) -> anyhow::Result<usize> {
+        Ok(AsyncCommands::publish(self, channel, payload).await?)
+    }
+}
+
+#[async_trait]
+impl Subscriber for redis::Client {
+    async fn subscribe(&self, channel: &str) -> anyhow::Result<UnboundedReceiver<Message>> {
+        let mut pubsub = self.get_async_connection().await?.into_pubsub();
+        pubsub.subscribe(channel).await?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut messages = pubsub.on_message();
+            while let Some(msg) = messages.next().await {
+                let payload = match msg.get_payload::<Vec<u8>>() {
+                    Ok(payload) => payload,
+                    Err(e) => {
+                        warn!("pubsub {} payload err: {:?}", msg.get_channel_name(), e);
+                        continue;
+                    }
+                };
+                let message = Message { channel: msg.get_channel_name().to_string(), payload };
+                if tx.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(rx)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Bytes(Vec<u8>),
+    Hash(HashMap<String, Vec<u8>>),
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    value: Value,
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn is_live(&self, now: Instant) -> bool {
+        self.expires.is_none_or(|e| e > now)
+    }
+}
+
+/// Process-local backend for tests and Redis-free runs, with TTLs, hashes and
+/// pub/sub. Clones share the same data.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryBackend {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    channels: Arc<Mutex<HashMap<String, Vec<UnboundedSender<Message>>>>>,
+    unavailable: Arc<AtomicBool>,
+}
+
+impl MemoryBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Make every call fail, as if the server were down.
+    pub fn set_unavailable(&self, unavailable: bool) {
+        self.unavailable.store(unavailable, Ordering::SeqCst);
+    }
+
+    /// Store raw bytes, e.g. to simulate a value written by another program.
+    pub fn insert_raw(&self, key: &str, value: Vec<u8>) {
+        self.entries.lock().unwrap().insert(key.to_string(), Entry { value: Value::Bytes(value), expires: None });
+    }
+
+    fn check(&self) -> anyhow::Result<()> {
+        if self.unavailable.load(Ordering::SeqCst) {
+            return Err(anyhow!("memory backend unavailable"));
+        }
+        Ok(())
+    }
+
+    /// The live entry under `key`, dropping it if it has expired.
+    fn live<'a>(entries: &'a mut HashMap<String, Entry>, key: &str, now: Instant) -> Option<&'a mut Entry> {
+        if entries.get(key).is_some_and(|e| !e.is_live(now)) {
+            entries.remove(key);
+        }
+        entries.get_mut(key)
+    }
+
+    fn bytes(entries: &mut HashMap<String, Entry>, key: &str, now: Instant) -> anyhow::Result<Option<Vec<u8>>> {
+        match Self::live(entries, key, now).map(|e| &e.value) {
+            None => Ok(None),
+            Some(Value::Bytes(bytes)) => Ok(Some(bytes.clone())),
+            Some(Value::Hash(_)) => Err(wrong_type(key)),
+        }
+    }
+
+    fn apply(entries: &mut HashMap<String, Entry>, op: WriteOp, now: Instant) -> anyhow::Result<()> {
+        match op {
+            WriteOp::SetEx { key, value, seconds } => {
+                let expires = Some(now + Duration::from_secs(seconds as u64));
+                entries.insert(key, Entry { value: Value::Bytes(value), expires });
+            }
+            // Redis rejects an HSET without fields, `exec` skips it there too
+            WriteOp::HSet { fields, .. } if fields.is_empty() => {}
+            WriteOp::HSet { key, fields } => {
+                let entry = match Self::live(entries, &key, now) {
+                    Some(entry) => entry,
+                    None => entries.entry(key.clone()).or_insert(Entry { value: Value::Hash(HashMap::new()), expires: None }),
+                };
+                match &mut entry.value {
+                    Value::Hash(hash) => hash.extend(fields),
+                    Value::Bytes(_) => return Err(wrong_type(&key)),
+                }
+            }
+            WriteOp::HDel { key, fields } => {
+                let emptied = match Self::live(entries, &key, now).map(|e| &mut e.value) {
+                    None => false,
+                    Some(Value::Hash(hash)) => {
+                        for field in &fields {
+                            hash.remove(field);
+                        }
+                        hash.is_empty()
+                    }
+                    Some(Value::Bytes(_)) => return Err(wrong_type(&key)),
+                };
+                // like Redis, a hash without fields is no key at all
+                if emptied {
+                    entries.remove(&key);
+                }
+            }
+            WriteOp::Expire { key, seconds } => {
+                if let Some(entry) = Self::live(entries, &key, now) {
+                    entry.expires = Some(now + Duration::from_secs(seconds as u64));
+                }
+            }
+            WriteOp::Del { key } => {
+                entries.remove(&key);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn wrong_type(key: &str) -> anyhow::Error {
+    anyhow!("WRONGTYPE {} holds another kind of value", key)
+}
+
+#[async_trait]
+impl CacheBackend for MemoryBackend {
+    async fn get_bytes(&mut self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        self.check()?;
+        Self::bytes(&mut self.entries.lock().unwrap(), key, Instant::now())
+    }
+
+    async fn set_bytes(&mut self, key: &str, value: Vec<u8>, ttl: Option<Duration>) -> anyhow::Result<()> {
+        self.check()?;
+        let expires = ttl.map(|ttl| Instant::now() + ttl);
+        self.entries.lock().unwrap().insert(key.to_string(), Entry { value: Value::Bytes(value), expires });
+        Ok(())
+    }
+
+    async fn pttl(&mut self, key: &str) -> anyhow::Result<i64> {
+        self.check()?;
+        let now = Instant::now();
+        match Self::live(&mut self.entries.lock().unwrap(), key, now) {
+            None => Ok(-2),
+            Some(Entry { expires: None, .. }) => Ok(-1),
+            Some(Entry { expires: Some(at), .. }) => Ok(at.duration_since(now).as_millis() as i64),
+        }
+    }
+
+    async fn get_many_bytes(&mut self, keys: &[String]) -> anyhow::Result<Vec<Option<Vec<u8>>>> {
+        self.check()?;
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        // MGET reports keys of another type as nil rather than failing
+        Ok(keys.iter().map(|key| Self::bytes(&mut entries, key, now).unwrap_or(None)).collect())
+    }
+
+    async fn hgetall_bytes(&mut self, key: &str) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+        self.check()?;
+        let mut entries = self.entries.lock().unwrap();
+        match Self::live(&mut entries, key, Instant::now()).map(|e| &e.value) {
+            None => Ok(vec![]),
+            Some(Value::Hash(hash)) => Ok(hash.iter().map(|(f, v)| (f.clone(), v.clone())).collect()),
+            Some(Value::Bytes(_)) => Err(wrong_type(key)),
+        }
+    }
+
+    async fn exec(&mut self, ops: Vec<WriteOp>) -> anyhow::Result<()> {
+        self.check()?;
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        // apply to copies of the keys written so a failing op leaves nothing behind
+        let mut staged = HashMap::new();
+        let mut touched = HashSet::new();
+        for op in ops {
+            if touched.insert(op.key().to_string()) {
+                if let Some(entry) = entries.get(op.key()) {
+                    staged.insert(op.key().to_string(), entry.clone());
+                }
+            }
+            Self::apply(&mut staged, op, now)?;
+        }
+        for key in touched {
+            match staged.remove(&key) {
+                Some(entry) => entries.insert(key, entry),
+                None => entries.remove(&key),
+            };
+        }
+        Ok(())
+    }
+
+    async fn try_lock(&mut self, key: &str, token: &str, millis: usize) -> anyhow::Result<bool> {
+        self.check()?;
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.get(key).is_some_and(|e| e.is_live(now)) {
+            return Ok(false);
+        }
+        let expires = Some(now + Duration::from_millis(millis as u64));
+        entries.insert(key.to_string(), Entry { value: Value::Bytes(token.as_bytes().to_vec()), expires });
+        Ok(true)
+    }
+
+    async fn unlock(&mut self, key: &str, token: &str) -> anyhow::Result<bool> {
+        self.check()?;
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(e) if e.is_live(now) && matches!(&e.value, Value::Bytes(held) if held == token.as_bytes()) => {
+                entries.remove(key);
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    async fn publish(&mut self, channel: &str, payload: Vec<u8>) -> anyhow::Result<usize> {
+        self.check()?;
+        let mut channels = self.channels.lock().unwrap();
+        let subscribers = match channels.get_mut(channel) {
+            Some(subscribers) => subscribers,
+            None => return Ok(0),
+        };
+        let message = Message { channel: channel.to_string(), payload };
+        subscribers.retain(|tx| tx.send(message.clone()).is_ok());
+        Ok(subscribers.len())
+    }
+}
+
+#[async_trait]
+impl Subscriber for MemoryBackend {
+    async fn subscribe(&self, channel: &str) -> anyhow::Result<UnboundedReceiver<Message>> {
+        self.check()?;
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.channels.lock().unwrap().entry(channel.to_string()).or_default().push(tx);
+        Ok(rx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_ttls() {
+        let mut backend = MemoryBackend::new();
+        backend.set_bytes("short", b"1".to_vec(), Some(Duration::from_millis(30))).await.unwrap();
+        backend.set_bytes("forever", b"2".to_vec(), None).await.unwrap();
+        assert!(backend.pttl("short").await.unwrap() > 0);
+        assert_eq!(backend.pttl("forever").await.unwrap(), -1);
+        assert_eq!(backend.pttl("missing").await.unwrap(), -2);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(backend.get_bytes("short").await.unwrap(), None);
+        assert_eq!(backend.pttl("short").await.unwrap(), -2);
+        assert_eq!(backend.get_bytes("forever").await.unwrap(), Some(b"2".to_vec()));
+
+        // hashes expire as a whole
+        backend.exec(vec![
+            WriteOp::HSet { key: "h".into(), fields: vec![("f".into(), b"v".to_vec())] },
+            WriteOp::Expire { key: "h".into(), seconds: 0 },
+        ]).await.unwrap();
+        assert!(backend.hgetall_bytes("h").await.unwrap().is_empty());
+        assert!(backend.get_bytes("h").await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_memory_pubsub() {
+        let mut backend = MemoryBackend::new();
+        assert_eq!(backend.publish("news", b"nobody".to_vec()).await.unwrap(), 0);
+
+        let mut first = backend.subscribe("news").await.unwrap();
+        let second = backend.subscribe("news").await.unwrap();
+        assert_eq!(backend.publish("news", b"hello".to_vec()).await.unwrap(), 2);
+        assert_eq!(first.recv().await.unwrap(), Message { channel: "news".into(), payload: b"hello".to_vec() });
+
+        drop(second);
+        assert_eq!(backend.publish("news", b"again".to_vec()).await.unwrap(), 1);
+        assert_eq!(backend.publish("other", b"x".to_vec()).await.unwrap(), 0);
+    }
+}
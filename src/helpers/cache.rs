@@ -1,47 +1,1000 @@
-use redis::aio::MultiplexedConnection;
-use redis::AsyncCommands;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use anyhow::anyhow;
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{de::DeserializeOwned, Serialize};
+use tokio::sync::mpsc::UnboundedReceiver;
+use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::helpers::backend::{CacheBackend, Message, Subscriber, WriteOp};
+use crate::helpers::codec::{self, Decoded, Format, Header};
+use crate::helpers::keys;
+use crate::helpers::near_cache::Local;
+
+/// Outcome of reading one key, keeping "not there" apart from "couldn't tell".
+#[derive(Debug)]
+pub enum Lookup<T> {
+    Hit(T),
+    /// No value, an expired one, or one written under another schema version.
+    Miss,
+    /// A value is there but doesn't decode under the expected format.
+    Corrupt(anyhow::Error),
+    /// The backend itself failed, e.g. Redis is down.
+    BackendError(anyhow::Error),
+}
+
+/// What `get_or_create_with` does when a lookup is `Corrupt` or a `BackendError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    /// Return the error without calling the creator.
+    Propagate,
+    /// Call the creator. A corrupt value is overwritten; after a backend error
+    /// nothing is written.
+    Bypass,
+    /// Return the last value this process read or created for the key, or
+    /// behave like `Bypass` when there is none. Every read keeps a copy of the
+    /// encoded value in memory, for up to `STALE_CAPACITY` keys and `STALE_MAX_AGE`,
+    /// so budget for the largest values served this way.
+    ServeStale,
+}
+
+// most keys kept for `OnError::ServeStale`, the oldest written goes first
+pub const STALE_CAPACITY: usize = 10_000;
+// how long a value is kept for `OnError::ServeStale` after it was last read or created
+pub const STALE_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+// last good encoded value per key, kept only for `OnError::ServeStale` call sites
+static STALE: Lazy<Mutex<Local<Vec<u8>>>> = Lazy::new(|| Mutex::new(Local::new()));
+
+pub async fn lookup<B, T>(backend: &mut B, key: &str, format: Format) -> Lookup<T>
+    where
+        B: CacheBackend + ?Sized,
+        T: DeserializeOwned,
+{
+    match backend.get_bytes(key).await {
+        Ok(Some(bytes)) => decode_lookup(key, format, &bytes),
+        Ok(None) => Lookup::Miss,
+        Err(e) => Lookup::BackendError(e),
+    }
+}
+
+fn decode_lookup<T>(key: &str, format: Format, bytes: &[u8]) -> Lookup<T>
+    where
+        T: DeserializeOwned,
+{
+    match codec::decode(format, bytes) {
+        Ok(Decoded::Value(item)) => Lookup::Hit(item),
+        Ok(Decoded::Outdated(header, _)) => {
+            warn!("cache {} is schema v{}, want v{}: miss", key, header.version, format.version);
+            Lookup::Miss
+        }
+        Ok(Decoded::Unrecognized) => Lookup::Miss,
+        Err(e) => Lookup::Corrupt(e),
+    }
+}
+
+/// Read many keys in one round trip. A missing, outdated or corrupt value is `None`;
+/// corrupt ones are logged.
+pub async fn mget<B, T>(backend: &mut B, keys: &[String], format: Format) -> anyhow::Result<Vec<Option<T>>>
+    where
+        B: CacheBackend + ?Sized,
+        T: DeserializeOwned,
+{
+    let values = backend.get_many_bytes(keys).await?;
+    Ok(keys
+        .iter()
+        .zip(values)
+        .map(|(key, bytes)| match bytes.map(|b| decode_lookup(key, format, &b)) {
+            Some(Lookup::Hit(item)) => Some(item),
+            Some(Lookup::Corrupt(e)) => {
+                warn!("cache {} corrupt: {:?}", key, e);
+                None
+            }
+            _ => None,
+        })
+        .collect())
+}
+
+/// Write many keys with the same expiry in one round trip.
+pub async fn mset_ex<B, I, K, T>(backend: &mut B, items: I, seconds: usize, format: Format) -> anyhow::Result<()>
+    where
+        B: CacheBackend + ?Sized,
+        I: IntoIterator<Item=(K, T)>,
+        K: Into<String>,
+        T: Serialize,
+{
+    let mut pipe = Pipeline::with_format(format);
+    for (key, value) in items {
+        pipe.set_ex(key, &value, seconds)?;
+    }
+    pipe.execute(backend).await
+}
 
-pub async fn get_or_create<'a, K, T, F, P>(
-    con: &mut MultiplexedConnection,
-    key: K,
+/// Set many fields of one hash in one round trip.
+pub async fn hset_many<B, I, F, T>(backend: &mut B, key: &str, fields: I, format: Format) -> anyhow::Result<()>
+    where
+        B: CacheBackend + ?Sized,
+        I: IntoIterator<Item=(F, T)>,
+        F: Into<String>,
+        T: Serialize,
+{
+    let mut pipe = Pipeline::with_format(format);
+    pipe.hset_many(key, fields)?;
+    pipe.execute(backend).await
+}
+
+/// Every field of a hash, decoded. Fields that don't decode under `format` are
+/// logged and left out.
+pub async fn hgetall_typed<B, T>(backend: &mut B, key: &str, format: Format) -> anyhow::Result<HashMap<String, T>>
+    where
+        B: CacheBackend + ?Sized,
+        T: DeserializeOwned,
+{
+    let mut fields = HashMap::new();
+    for (field, bytes) in backend.hgetall_bytes(key).await? {
+        match decode_lookup(key, format, &bytes) {
+            Lookup::Hit(item) => {
+                fields.insert(field, item);
+            }
+            Lookup::Corrupt(e) => warn!("cache {} field {} corrupt: {:?}", key, field, e),
+            _ => {}
+        }
+    }
+    Ok(fields)
+}
+
+/// Queues typed writes and sends them in one round trip, applied atomically.
+///
+/// ```ignore
+/// let mut pipe = Pipeline::new();
+/// pipe.set_ex(keys::price(platform, market, "BTCUSDT"), &price, 60)?
+///     .hset(keys::coin_symbols("BTC"), "BTCUSDT", &"USDT")?;
+/// pipe.execute(&mut redis).await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Pipeline {
+    format: Format,
+    ops: Vec<WriteOp>,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_format(format: Format) -> Self {
+        Self { format, ops: vec![] }
+    }
+
+    pub fn set_ex<K, T>(&mut self, key: K, value: &T, seconds: usize) -> anyhow::Result<&mut Self>
+        where
+            K: Into<String>,
+            T: Serialize + ?Sized,
+    {
+        let value = codec::encode(self.format, value)?;
+        self.ops.push(WriteOp::SetEx { key: key.into(), value, seconds });
+        Ok(self)
+    }
+
+    pub fn hset<K, F, T>(&mut self, key: K, field: F, value: &T) -> anyhow::Result<&mut Self>
+        where
+            K: Into<String>,
+            F: Into<String>,
+            T: Serialize + ?Sized,
+    {
+        self.hset_many(key, [(field, value)])
+    }
+
+    pub fn hset_many<K, I, F, T>(&mut self, key: K, fields: I) -> anyhow::Result<&mut Self>
+        where
+            K: Into<String>,
+            I: IntoIterator<Item=(F, T)>,
+            F: Into<String>,
+            T: Serialize,
+    {
+        let fields = fields
+            .into_iter()
+            .map(|(field, value)| Ok((field.into(), codec::encode(self.format, &value)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        self.ops.push(WriteOp::HSet { key: key.into(), fields });
+        Ok(self)
+    }
+
+    pub fn hdel<K, I, F>(&mut self, key: K, fields: I) -> &mut Self
+        where
+            K: Into<String>,
+            I: IntoIterator<Item=F>,
+            F: Into<String>,
+    {
+        let fields = fields.into_iter().map(Into::into).collect();
+        self.ops.push(WriteOp::HDel { key: key.into(), fields });
+        self
+    }
+
+    pub fn expire<K>(&mut self, key: K, seconds: usize) -> &mut Self
+        where
+            K: Into<String>,
+    {
+        self.ops.push(WriteOp::Expire { key: key.into(), seconds });
+        self
+    }
+
+    pub fn del<K>(&mut self, key: K) -> &mut Self
+        where
+            K: Into<String>,
+    {
+        self.ops.push(WriteOp::Del { key: key.into() });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    pub async fn execute<B>(self, backend: &mut B) -> anyhow::Result<()>
+        where
+            B: CacheBackend + ?Sized,
+    {
+        backend.exec(self.ops).await
+    }
+}
+
+/// `get_or_create_with` using the default format and `OnError::Propagate`.
+pub async fn get_or_create<B, T, F, P>(
+    backend: &mut B,
+    key: &str,
+    create_fn: F,
+) -> anyhow::Result<T>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize + DeserializeOwned,
+        F: Fn() -> P,
+        P: core::future::Future<Output=anyhow::Result<(T, usize)>>,
+{
+    get_or_create_with(backend, key, Format::default(), OnError::Propagate, create_fn).await
+}
+
+/// Return the cached value, or run `create_fn` and cache what it returns for its
+/// expiry in seconds. `on_error` decides what a corrupt value or a backend failure does.
+///
+/// Concurrent callers for the same key and format in this process share one run of
+/// `create_fn`.
+pub async fn get_or_create_with<B, T, F, P>(
+    backend: &mut B,
+    key: &str,
+    format: Format,
+    on_error: OnError,
+    create_fn: F,
+) -> anyhow::Result<T>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize + DeserializeOwned,
+        F: Fn() -> P,
+        P: core::future::Future<Output=anyhow::Result<(T, usize)>>,
+{
+    fetch(backend, key, format, on_error, None, create_fn).await
+}
+
+/// Like `get_or_create_with`, but also takes the key's `keys::lock` in Redis so that
+/// only one process runs `create_fn`. The others poll the cache until the value
+/// shows up, and create it themselves once `lock.wait` has passed.
+pub async fn get_or_create_locked<B, T, F, P>(
+    backend: &mut B,
+    key: &str,
+    format: Format,
+    on_error: OnError,
+    lock: Lock,
+    create_fn: F,
+) -> anyhow::Result<T>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize + DeserializeOwned,
+        F: Fn() -> P,
+        P: core::future::Future<Output=anyhow::Result<(T, usize)>>,
+{
+    fetch(backend, key, format, on_error, Some(lock), create_fn).await
+}
+
+/// Timing of the cross-process lock used by `get_or_create_locked`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lock {
+    /// Lock expiry, so a crashed holder doesn't block the key forever.
+    pub ttl: Duration,
+    /// How long to wait for another holder before creating anyway.
+    pub wait: Duration,
+    /// Interval between cache checks while waiting.
+    pub poll: Duration,
+}
+
+impl Default for Lock {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(10),
+            wait: Duration::from_secs(10),
+            poll: Duration::from_millis(50),
+        }
+    }
+}
+
+async fn fetch<B, T, F, P>(
+    backend: &mut B,
+    key: &str,
+    format: Format,
+    on_error: OnError,
+    lock: Option<Lock>,
     create_fn: F,
 ) -> anyhow::Result<T>
     where
-        K: redis::ToRedisArgs + Clone + Send + Sync + 'a,
+        B: CacheBackend + ?Sized,
+        T: Serialize + DeserializeOwned,
+        F: Fn() -> P,
+        P: core::future::Future<Output=anyhow::Result<(T, usize)>>,
+{
+    let write_back = match lookup(backend, key, format).await {
+        Lookup::Hit(item) => {
+            if on_error == OnError::ServeStale {
+                remember(key, format, &item);
+            }
+            return Ok(item);
+        }
+        Lookup::Miss => true,
+        Lookup::Corrupt(e) | Lookup::BackendError(e) if on_error == OnError::Propagate => return Err(e),
+        Lookup::Corrupt(e) => {
+            if let Some(item) = stale(key, format, on_error) {
+                warn!("cache {} corrupt, serving stale: {:?}", key, e);
+                return Ok(item);
+            }
+            warn!("cache {} corrupt, recreating: {:?}", key, e);
+            true
+        }
+        Lookup::BackendError(e) => {
+            if let Some(item) = stale(key, format, on_error) {
+                warn!("cache {} unavailable, serving stale: {:?}", key, e);
+                return Ok(item);
+            }
+            warn!("cache {} unavailable, bypassing: {:?}", key, e);
+            false
+        }
+    };
+
+    let flight_key = (key.to_string(), format);
+    let flight = FLIGHTS.entry(flight_key.clone()).or_default().clone();
+    let result = flight
+        .get_or_init(|| async {
+            create::<_, T, _, _>(backend, key, format, on_error, write_back, lock, &create_fn)
+                .await
+                .map_err(Arc::new)
+        })
+        .await
+        .clone();
+    FLIGHTS.remove_if(&flight_key, |_, f| Arc::ptr_eq(f, &flight));
+
+    let bytes = result.map_err(|e| anyhow!("{:#}", e))?;
+    let item = match codec::decode(format, &bytes) {
+        Ok(Decoded::Value(item)) => item,
+        // the flight was created for another type under the same format
+        _ => {
+            warn!("cache {} single flight value doesn't decode, creating", key);
+            let bytes = create::<_, T, _, _>(backend, key, format, on_error, write_back, lock, &create_fn).await?;
+            match codec::decode(format, &bytes)? {
+                Decoded::Value(item) => item,
+                _ => return Err(anyhow!("cache {} decode err", key)),
+            }
+        }
+    };
+    if on_error == OnError::ServeStale {
+        remember(key, format, &item);
+    }
+    Ok(item)
+}
+
+// encoded result of one creation, shared by every caller that waited on it
+type Flight = Arc<OnceCell<Result<Vec<u8>, Arc<anyhow::Error>>>>;
+
+// one in-flight creation per key and format; callers arriving meanwhile await its result
+static FLIGHTS: Lazy<DashMap<(String, Format), Flight>> = Lazy::new(DashMap::new);
+
+/// Run `create_fn` and write the result back, returning it encoded.
+async fn create<B, T, F, P>(
+    backend: &mut B,
+    key: &str,
+    format: Format,
+    on_error: OnError,
+    write_back: bool,
+    lock: Option<Lock>,
+    create_fn: F,
+) -> anyhow::Result<Vec<u8>>
+    where
+        B: CacheBackend + ?Sized,
         T: Serialize + DeserializeOwned,
         F: Fn() -> P,
         P: core::future::Future<Output=anyhow::Result<(T, usize)>>,
 {
-    if let Ok(item) = get(con, key.clone()).await {
-        Ok(item)
-    } else {
-        let (item, expiry) = create_fn().await?;
-        set_ex(con, key, &item, expiry).await?;
-        Ok(item)
+    let lock_key = keys::lock(key);
+    let token = match lock {
+        Some(lock) if write_back => match acquire::<_, T>(backend, key, &lock_key, format, lock).await {
+            Acquired::Locked(token) => Some(token),
+            Acquired::Cached(bytes) => return Ok(bytes),
+            Acquired::Skipped => None,
+        },
+        _ => None,
+    };
+
+    let created = create_fn().await;
+    let stored = match created {
+        Ok((item, expiry)) => store(backend, key, format, on_error, write_back, &item, expiry).await,
+        Err(e) => Err(e),
+    };
+
+    if let Some(token) = token {
+        match backend.unlock(&lock_key, &token).await {
+            Ok(true) => {}
+            Ok(false) => warn!("cache lock {} expired before release", lock_key),
+            Err(e) => warn!("cache lock {} release err: {:?}", lock_key, e),
+        }
+    }
+    stored
+}
+
+async fn store<B, T>(
+    backend: &mut B,
+    key: &str,
+    format: Format,
+    on_error: OnError,
+    write_back: bool,
+    item: &T,
+    expiry: usize,
+) -> anyhow::Result<Vec<u8>>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize,
+{
+    let bytes = codec::encode(format, item)?;
+    if write_back {
+        if let Err(e) = backend.set_ex_bytes(key, bytes.clone(), expiry).await {
+            if on_error == OnError::Propagate {
+                return Err(e);
+            }
+            warn!("cache {} write failed: {:?}", key, e);
+        }
+    }
+    Ok(bytes)
+}
+
+enum Acquired {
+    Locked(String),
+    /// Another process filled the key while we waited.
+    Cached(Vec<u8>),
+    /// Lock unavailable or wait exceeded; create without it.
+    Skipped,
+}
+
+async fn acquire<B, T>(backend: &mut B, key: &str, lock_key: &str, format: Format, lock: Lock) -> Acquired
+    where
+        B: CacheBackend + ?Sized,
+        T: DeserializeOwned,
+{
+    let token = lock_token();
+    let started = Instant::now();
+    loop {
+        match backend.try_lock(lock_key, &token, lock.ttl.as_millis() as usize).await {
+            Ok(true) => return Acquired::Locked(token),
+            Ok(false) => {}
+            Err(e) => {
+                warn!("cache lock {} err, creating unlocked: {:?}", lock_key, e);
+                return Acquired::Skipped;
+            }
+        }
+        if started.elapsed() >= lock.wait {
+            warn!("cache lock {} still held after {:?}, creating unlocked", lock_key, lock.wait);
+            return Acquired::Skipped;
+        }
+        tokio::time::sleep(lock.poll).await;
+        if let Ok(Some(bytes)) = backend.get_bytes(key).await {
+            if let Ok(Decoded::Value(_)) = codec::decode::<T>(format, &bytes) {
+                return Acquired::Cached(bytes);
+            }
+        }
+    }
+}
+
+fn lock_token() -> String {
+    let mut bytes = [0u8; 16];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        // still unique per process and call, only weaker against guessing
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos();
+        return format!("{}-{}", std::process::id(), nanos);
+    }
+    hex::encode(bytes)
+}
+
+fn remember<T>(key: &str, format: Format, item: &T)
+    where
+        T: Serialize,
+{
+    if let Ok(bytes) = codec::encode(format, item) {
+        let expires = Instant::now() + STALE_MAX_AGE;
+        STALE.lock().unwrap().insert(key.to_string(), bytes, expires, STALE_CAPACITY);
     }
 }
 
-pub async fn get<'a, K, T>(con: &mut MultiplexedConnection, key: K) -> anyhow::Result<T>
+fn stale<T>(key: &str, format: Format, on_error: OnError) -> Option<T>
     where
-        K: redis::ToRedisArgs + Send + Sync + 'a,
         T: DeserializeOwned,
 {
-    let bytes: Vec<u8> = con.get(key).await?;
-    Ok(bincode::deserialize(&bytes)?)
+    if on_error != OnError::ServeStale {
+        return None;
+    }
+    let mut stale = STALE.lock().unwrap();
+    let bytes = stale.get(key, Instant::now())?;
+    match codec::decode(format, bytes) {
+        Ok(Decoded::Value(item)) => Some(item),
+        _ => None,
+    }
+}
+
+/// Read a value written with the default format. A missing or outdated value is an error.
+pub async fn get<B, T>(backend: &mut B, key: &str) -> anyhow::Result<T>
+    where
+        B: CacheBackend + ?Sized,
+        T: DeserializeOwned,
+{
+    get_with(backend, key, Format::default())
+        .await?
+        .ok_or_else(|| anyhow!("cache miss"))
 }
 
-pub async fn set_ex<'a, K, T>(
-    con: &mut MultiplexedConnection,
-    key: K,
+/// Read a value stored under `format`. Values written under another schema version,
+/// or without a header, are reported as a miss.
+pub async fn get_with<B, T>(backend: &mut B, key: &str, format: Format) -> anyhow::Result<Option<T>>
+    where
+        B: CacheBackend + ?Sized,
+        T: DeserializeOwned,
+{
+    match lookup(backend, key, format).await {
+        Lookup::Hit(item) => Ok(Some(item)),
+        Lookup::Miss => Ok(None),
+        Lookup::Corrupt(e) | Lookup::BackendError(e) => Err(e),
+    }
+}
+
+/// Like `get_with`, but values under another schema version are passed to `migrate`.
+/// A migrated value is written back in `format`, keeping the key's remaining TTL.
+pub async fn get_migrating<B, T, M>(
+    backend: &mut B,
+    key: &str,
+    format: Format,
+    migrate: M,
+) -> anyhow::Result<Option<T>>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize + DeserializeOwned,
+        M: Fn(Header, &[u8]) -> anyhow::Result<Option<T>>,
+{
+    let bytes = match backend.get_bytes(key).await? {
+        None => return Ok(None),
+        Some(bytes) => bytes,
+    };
+    let item = match codec::decode(format, &bytes)? {
+        Decoded::Value(item) => return Ok(Some(item)),
+        Decoded::Unrecognized => return Ok(None),
+        Decoded::Outdated(header, payload) => match migrate(header, payload)? {
+            Some(item) => item,
+            None => return Ok(None),
+        },
+    };
+
+    let ttl = backend.pttl(key).await?;
+    let encoded = codec::encode(format, &item)?;
+    if ttl > 0 {
+        backend.set_bytes(key, encoded, Some(Duration::from_millis(ttl as u64))).await?;
+    } else if ttl == -1 {
+        backend.set_bytes(key, encoded, None).await?;
+    }
+    Ok(Some(item))
+}
+
+pub async fn set_ex<B, T>(
+    backend: &mut B,
+    key: &str,
     value: &T,
     seconds: usize,
 ) -> anyhow::Result<()>
     where
-        K: redis::ToRedisArgs + Send + Sync + 'a,
-        T: Serialize,
+        B: CacheBackend + ?Sized,
+        T: Serialize + ?Sized,
+{
+    set_ex_with(backend, key, value, seconds, Format::default()).await
+}
+
+pub async fn set_ex_with<B, T>(
+    backend: &mut B,
+    key: &str,
+    value: &T,
+    seconds: usize,
+    format: Format,
+) -> anyhow::Result<()>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize + ?Sized,
+{
+    backend.set_ex_bytes(key, codec::encode(format, value)?, seconds).await
+}
+
+/// Publish a value encoded like cached ones. Returns how many subscribers got it.
+pub async fn publish<B, T>(backend: &mut B, channel: &str, value: &T, format: Format) -> anyhow::Result<usize>
+    where
+        B: CacheBackend + ?Sized,
+        T: Serialize + ?Sized,
+{
+    backend.publish(channel, codec::encode(format, value)?).await
+}
+
+pub async fn subscribe<S, T>(subscriber: &S, channel: &str, format: Format) -> anyhow::Result<Subscription<T>>
+    where
+        S: Subscriber + ?Sized,
+        T: DeserializeOwned,
 {
-    con.set_ex(key, bincode::serialize(value)?, seconds).await?;
+    Ok(Subscription {
+        rx: subscriber.subscribe(channel).await?,
+        format,
+        item: PhantomData,
+    })
+}
 
-    Ok(())
+/// Typed side of a `subscribe`. Messages that don't decode under its format are
+/// logged and skipped.
+#[derive(Debug)]
+pub struct Subscription<T> {
+    rx: UnboundedReceiver<Message>,
+    format: Format,
+    item: PhantomData<fn() -> T>,
+}
+
+impl<T> Subscription<T>
+    where
+        T: DeserializeOwned,
+{
+    /// The next message, or `None` once the subscription has closed.
+    pub async fn recv(&mut self) -> Option<T> {
+        while let Some(message) = self.rx.recv().await {
+            match decode_lookup(&message.channel, self.format, &message.payload) {
+                Lookup::Hit(item) => return Some(item),
+                Lookup::Corrupt(e) => warn!("pubsub {} message corrupt: {:?}", message.channel, e),
+                _ => warn!("pubsub {} message in another format", message.channel),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::backend::MemoryBackend;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    async fn run(backend: &mut MemoryBackend, key: &str, on_error: OnError, calls: &AtomicUsize, value: u64) -> anyhow::Result<u64> {
+        get_or_create_with(backend, key, Format::default(), on_error, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok((value, 60))
+        }).await
+    }
+
+    #[tokio::test]
+    async fn test_lookup_results() {
+        let mut backend = MemoryBackend::new();
+        assert!(matches!(lookup::<_, u64>(&mut backend, "k", Format::default()).await, Lookup::Miss));
+
+        backend.set_ex_bytes("k", codec::encode(Format::default(), &7u64).unwrap(), 60).await.unwrap();
+        assert!(matches!(lookup::<_, u64>(&mut backend, "k", Format::default()).await, Lookup::Hit(7)));
+        assert!(matches!(lookup::<_, u64>(&mut backend, "k", Format::bincode(1)).await, Lookup::Miss));
+
+        backend.insert_raw("k", codec::encode(Format::default(), &1u8).unwrap());
+        assert!(matches!(lookup::<_, u64>(&mut backend, "k", Format::default()).await, Lookup::Corrupt(_)));
+
+        backend.set_unavailable(true);
+        assert!(matches!(lookup::<_, u64>(&mut backend, "k", Format::default()).await, Lookup::BackendError(_)));
+    }
+
+    #[tokio::test]
+    async fn test_miss_then_hit() {
+        let mut backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        assert_eq!(run(&mut backend, "miss-hit", OnError::Propagate, &calls, 1).await.unwrap(), 1);
+        assert_eq!(run(&mut backend, "miss-hit", OnError::Propagate, &calls, 2).await.unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_backend_error_policies() {
+        let mut backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+
+        backend.set_unavailable(true);
+        assert!(run(&mut backend, "outage", OnError::Propagate, &calls, 1).await.is_err());
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        assert_eq!(run(&mut backend, "outage", OnError::Bypass, &calls, 2).await.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        backend.set_unavailable(false);
+        assert_eq!(run(&mut backend, "outage", OnError::ServeStale, &calls, 3).await.unwrap(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        backend.set_unavailable(true);
+        assert_eq!(run(&mut backend, "outage", OnError::ServeStale, &calls, 4).await.unwrap(), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_policies() {
+        let mut backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        backend.insert_raw("corrupt", codec::encode(Format::default(), &1u8).unwrap());
+
+        assert!(run(&mut backend, "corrupt", OnError::Propagate, &calls, 1).await.is_err());
+        assert_eq!(run(&mut backend, "corrupt", OnError::Bypass, &calls, 2).await.unwrap(), 2);
+        // the corrupt value was overwritten
+        assert_eq!(run(&mut backend, "corrupt", OnError::Propagate, &calls, 3).await.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_single_flight() {
+        let backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        let callers = (0..10).map(|_| {
+            let mut backend = backend.clone();
+            let calls = &calls;
+            async move {
+                get_or_create(&mut backend, "flight", || async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok((42u64, 60))
+                }).await
+            }
+        });
+        for result in futures::future::join_all(callers).await {
+            assert_eq!(result.unwrap(), 42);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        assert!(FLIGHTS.get(&("flight".to_string(), Format::default())).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_per_format() {
+        let backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        let caller = |format: Format, value: u64| {
+            let mut backend = backend.clone();
+            let calls = &calls;
+            async move {
+                get_or_create_with(&mut backend, "flight-format", format, OnError::Propagate, || async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok((value, 60))
+                }).await
+            }
+        };
+        let (a, b) = tokio::join!(caller(Format::default(), 1), caller(Format::bincode(1), 2));
+        assert_eq!(a.unwrap(), 1);
+        assert_eq!(b.unwrap(), 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_shares_errors() {
+        let backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        let callers = (0..3).map(|_| {
+            let mut backend = backend.clone();
+            let calls = &calls;
+            async move {
+                get_or_create::<_, u64, _, _>(&mut backend, "flight-err", || async {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    Err(anyhow!("weight limit"))
+                }).await
+            }
+        });
+        for result in futures::future::join_all(callers).await {
+            assert!(result.unwrap_err().to_string().contains("weight limit"));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_lock_waits_for_other_process() {
+        let mut backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        let lock = Lock { ttl: Duration::from_secs(5), wait: Duration::from_secs(5), poll: Duration::from_millis(10) };
+
+        // another process holds the lock and fills the key shortly after
+        assert!(backend.try_lock(&keys::lock("locked"), "other", 5000).await.unwrap());
+        let mut other = backend.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            other.set_ex_bytes("locked", codec::encode(Format::default(), &7u64).unwrap(), 60).await.unwrap();
+            other.unlock(&keys::lock("locked"), "other").await.unwrap();
+        });
+
+        let item: u64 = get_or_create_locked(&mut backend, "locked", Format::default(), OnError::Propagate, lock, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok((1u64, 60))
+        }).await.unwrap();
+        assert_eq!(item, 7);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn test_lock_released_and_wait_bounded() {
+        let mut backend = MemoryBackend::new();
+        let calls = AtomicUsize::new(0);
+        let lock = Lock { ttl: Duration::from_secs(5), wait: Duration::from_millis(30), poll: Duration::from_millis(10) };
+
+        let item: u64 = get_or_create_locked(&mut backend, "released", Format::default(), OnError::Propagate, lock, || async {
+            Ok((1u64, 60))
+        }).await.unwrap();
+        assert_eq!(item, 1);
+        assert!(backend.try_lock(&keys::lock("released"), "next", 1000).await.unwrap());
+
+        // a holder that never finishes only delays us by `wait`
+        assert!(backend.try_lock(&keys::lock("stuck"), "other", 5000).await.unwrap());
+        let item: u64 = get_or_create_locked(&mut backend, "stuck", Format::default(), OnError::Propagate, lock, || async {
+            calls.fetch_add(1, Ordering::SeqCst);
+            Ok((2u64, 60))
+        }).await.unwrap();
+        assert_eq!(item, 2);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_batch_operations() {
+        let mut backend = MemoryBackend::new();
+        let format = Format::json(1);
+
+        mset_ex(&mut backend, [("a", 1u64), ("b", 2u64)], 60, format).await.unwrap();
+        backend.insert_raw("bad", codec::encode(format, &"x").unwrap());
+        let keys: Vec<String> = ["a", "missing", "b", "bad"].iter().map(|k| k.to_string()).collect();
+        let values: Vec<Option<u64>> = mget(&mut backend, &keys, format).await.unwrap();
+        assert_eq!(values, vec![Some(1), None, Some(2), None]);
+
+        hset_many(&mut backend, "coins", [("BTCUSDT", "USDT"), ("BTCBUSD", "BUSD")], format).await.unwrap();
+        let fields: HashMap<String, String> = hgetall_typed(&mut backend, "coins", format).await.unwrap();
+        assert_eq!(fields.len(), 2);
+        assert_eq!(fields["BTCBUSD"], "BUSD");
+        assert!(hgetall_typed::<_, String>(&mut backend, "nothing", format).await.unwrap().is_empty());
+
+        // values are readable by the single-key helpers
+        assert!(matches!(lookup::<_, u64>(&mut backend, "b", format).await, Lookup::Hit(2)));
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_is_atomic() {
+        let mut backend = MemoryBackend::new();
+        let mut pipe = Pipeline::new();
+        pipe.set_ex("p:a", &1u64, 60).unwrap()
+            .hset_many("p:h", [("f", "v"), ("g", "w")]).unwrap()
+            .hdel("p:h", ["g"])
+            .del("p:gone");
+        assert_eq!(pipe.len(), 4);
+        pipe.execute(&mut backend).await.unwrap();
+        assert!(matches!(lookup::<_, u64>(&mut backend, "p:a", Format::default()).await, Lookup::Hit(1)));
+        let fields: HashMap<String, String> = hgetall_typed(&mut backend, "p:h", Format::default()).await.unwrap();
+        assert_eq!(fields.keys().collect::<Vec<_>>(), vec!["f"]);
+
+        // a hash write onto a plain key fails, and the earlier write in the batch is dropped
+        let mut pipe = Pipeline::new();
+        pipe.set_ex("p:b", &2u64, 60).unwrap()
+            .hset("p:a", "f", &"v").unwrap();
+        assert!(pipe.execute(&mut backend).await.is_err());
+        assert!(matches!(lookup::<_, u64>(&mut backend, "p:b", Format::default()).await, Lookup::Miss));
+        assert!(matches!(lookup::<_, u64>(&mut backend, "p:a", Format::default()).await, Lookup::Hit(1)));
+
+        // a delete in a batch applies, keys outside it are left alone
+        let mut pipe = Pipeline::new();
+        pipe.del("p:a").hset("p:h", "g", &"w").unwrap();
+        pipe.execute(&mut backend).await.unwrap();
+        assert!(matches!(lookup::<_, u64>(&mut backend, "p:a", Format::default()).await, Lookup::Miss));
+        let fields: HashMap<String, String> = hgetall_typed(&mut backend, "p:h", Format::default()).await.unwrap();
+        assert_eq!(fields.len(), 2);
+    }
+
+    // what both backends must agree on for a batch of writes
+    async fn check_pipeline<B>(backend: &mut B)
+        where
+            B: CacheBackend,
+    {
+        let mut pipe = Pipeline::new();
+        pipe.del("pipe:a").del("pipe:h").del("pipe:empty");
+        pipe.execute(backend).await.unwrap();
+
+        let no_fields: [(&str, &str); 0] = [];
+        let mut pipe = Pipeline::new();
+        pipe.set_ex("pipe:a", &1u64, 60).unwrap()
+            .hset_many("pipe:h", [("f", "v"), ("g", "w")]).unwrap()
+            .hdel("pipe:h", ["g"])
+            .hset_many("pipe:empty", no_fields).unwrap()
+            .hdel("pipe:h", Vec::<String>::new());
+        pipe.execute(backend).await.unwrap();
+        assert!(matches!(lookup::<_, u64>(backend, "pipe:a", Format::default()).await, Lookup::Hit(1)));
+        let fields: HashMap<String, String> = hgetall_typed(backend, "pipe:h", Format::default()).await.unwrap();
+        assert_eq!(fields.keys().collect::<Vec<_>>(), vec!["f"]);
+        // an empty hash write creates no key
+        assert_eq!(backend.pttl("pipe:empty").await.unwrap(), -2);
+
+        // nor does it touch a plain key
+        let mut pipe = Pipeline::new();
+        pipe.hset_many("pipe:a", no_fields).unwrap();
+        pipe.execute(backend).await.unwrap();
+        assert!(matches!(lookup::<_, u64>(backend, "pipe:a", Format::default()).await, Lookup::Hit(1)));
+
+        let mut pipe = Pipeline::new();
+        pipe.del("pipe:a").hdel("pipe:h", ["f"]);
+        pipe.execute(backend).await.unwrap();
+        assert_eq!(backend.pttl("pipe:a").await.unwrap(), -2);
+        assert_eq!(backend.pttl("pipe:h").await.unwrap(), -2);
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_memory() {
+        check_pipeline(&mut MemoryBackend::new()).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a Redis on 127.0.0.1:6379"]
+    async fn test_pipeline_redis() {
+        let mut redis = redis::Client::open("redis://127.0.0.1:6379").unwrap()
+            .get_multiplexed_tokio_connection()
+            .await.unwrap();
+        check_pipeline(&mut redis).await;
+    }
+
+    #[tokio::test]
+    async fn test_get_and_set_ex() {
+        let mut backend = MemoryBackend::new();
+        set_ex(&mut backend, "hello", "word", 10).await.unwrap();
+        assert_eq!(get::<_, String>(&mut backend, "hello").await.unwrap(), "word");
+        assert!(get::<_, String>(&mut backend, "nothing").await.is_err());
+
+        set_ex_with(&mut backend, "hello", "word", 10, Format::json(2)).await.unwrap();
+        assert_eq!(get_with::<_, String>(&mut backend, "hello", Format::json(1)).await.unwrap(), None);
+        assert_eq!(get_with::<_, String>(&mut backend, "hello", Format::json(2)).await.unwrap().unwrap(), "word");
+    }
+
+    #[tokio::test]
+    async fn test_get_migrating_keeps_ttl() {
+        let mut backend = MemoryBackend::new();
+        set_ex_with(&mut backend, "migrate", &3u32, 100, Format::bincode(1)).await.unwrap();
+
+        let item: Option<u64> = get_migrating(&mut backend, "migrate", Format::bincode(2), |header, payload| {
+            let old: u32 = header.codec.decode(payload)?;
+            Ok(Some(old as u64 * 10))
+        }).await.unwrap();
+        assert_eq!(item, Some(30));
+        assert_eq!(get_with::<_, u64>(&mut backend, "migrate", Format::bincode(2)).await.unwrap(), Some(30));
+        let ttl = backend.pttl("migrate").await.unwrap();
+        assert!(ttl > 90_000 && ttl <= 100_000, "{}", ttl);
+    }
+
+    #[tokio::test]
+    async fn test_typed_pubsub() {
+        let mut backend = MemoryBackend::new();
+        let mut updates: Subscription<(String, u64)> = subscribe(&backend, "updates", Format::msgpack(1)).await.unwrap();
+
+        backend.publish("updates", b"garbage".to_vec()).await.unwrap();
+        publish(&mut backend, "updates", &("BTCUSDT".to_string(), 7u64), Format::msgpack(1)).await.unwrap();
+        assert_eq!(updates.recv().await.unwrap(), ("BTCUSDT".to_string(), 7));
+    }
 }
@@ -0,0 +1,297 @@
+//! Serialization of cached values.
+//!
+//! Every value written through `helpers::cache` starts with a 4 byte header:
+//! a magic byte, the codec id and a big-endian schema version. Readers pick the
+//! codec from the header, so a call site can switch codecs without breaking values
+//! already in Redis, and a value written under another schema version is reported
+//! as outdated instead of failing to deserialize.
+
+use anyhow::anyhow;
+use serde::{de::DeserializeOwned, Serialize};
+
+pub const MAGIC: u8 = 0xec;
+pub const HEADER_LEN: usize = 4;
+
+/// A serialization format for cached values.
+pub trait Codec {
+    const KIND: CodecKind;
+
+    fn encode<T>(value: &T) -> anyhow::Result<Vec<u8>>
+        where
+            T: Serialize + ?Sized;
+
+    fn decode<T>(bytes: &[u8]) -> anyhow::Result<T>
+        where
+            T: DeserializeOwned;
+}
+
+/// Compact, Rust-only.
+pub struct Bincode;
+
+/// Readable by anything; the payload after the header is plain UTF-8 JSON.
+pub struct Json;
+
+/// Compact and cross-language. Structs are written as maps so fields can be added.
+pub struct MsgPack;
+
+impl Codec for Bincode {
+    const KIND: CodecKind = CodecKind::Bincode;
+
+    fn encode<T>(value: &T) -> anyhow::Result<Vec<u8>>
+        where
+            T: Serialize + ?Sized,
+    {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> anyhow::Result<T>
+        where
+            T: DeserializeOwned,
+    {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+impl Codec for Json {
+    const KIND: CodecKind = CodecKind::Json;
+
+    fn encode<T>(value: &T) -> anyhow::Result<Vec<u8>>
+        where
+            T: Serialize + ?Sized,
+    {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> anyhow::Result<T>
+        where
+            T: DeserializeOwned,
+    {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+impl Codec for MsgPack {
+    const KIND: CodecKind = CodecKind::MsgPack;
+
+    fn encode<T>(value: &T) -> anyhow::Result<Vec<u8>>
+        where
+            T: Serialize + ?Sized,
+    {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T>(bytes: &[u8]) -> anyhow::Result<T>
+        where
+            T: DeserializeOwned,
+    {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CodecKind {
+    Bincode,
+    Json,
+    MsgPack,
+}
+
+impl CodecKind {
+    pub const fn id(&self) -> u8 {
+        match self {
+            CodecKind::Bincode => 1,
+            CodecKind::Json => 2,
+            CodecKind::MsgPack => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<CodecKind> {
+        match id {
+            1 => Some(CodecKind::Bincode),
+            2 => Some(CodecKind::Json),
+            3 => Some(CodecKind::MsgPack),
+            _ => None,
+        }
+    }
+
+    pub fn encode<T>(&self, value: &T) -> anyhow::Result<Vec<u8>>
+        where
+            T: Serialize + ?Sized,
+    {
+        match self {
+            CodecKind::Bincode => Bincode::encode(value),
+            CodecKind::Json => Json::encode(value),
+            CodecKind::MsgPack => MsgPack::encode(value),
+        }
+    }
+
+    pub fn decode<T>(&self, bytes: &[u8]) -> anyhow::Result<T>
+        where
+            T: DeserializeOwned,
+    {
+        match self {
+            CodecKind::Bincode => Bincode::decode(bytes),
+            CodecKind::Json => Json::decode(bytes),
+            CodecKind::MsgPack => MsgPack::decode(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub codec: CodecKind,
+    pub version: u16,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let v = self.version.to_be_bytes();
+        [MAGIC, self.codec.id(), v[0], v[1]]
+    }
+
+    /// Split a stored value into its header and payload. `None` when the bytes were
+    /// not written through this module.
+    pub fn split(bytes: &[u8]) -> Option<(Header, &[u8])> {
+        if bytes.len() < HEADER_LEN || bytes[0] != MAGIC {
+            return None;
+        }
+        let codec = CodecKind::from_id(bytes[1])?;
+        let version = u16::from_be_bytes([bytes[2], bytes[3]]);
+        Some((Header { codec, version }, &bytes[HEADER_LEN..]))
+    }
+}
+
+/// How one call site stores its values: the codec to write with and the schema
+/// version of the type. Bump the version whenever the type's layout changes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Format {
+    pub codec: CodecKind,
+    pub version: u16,
+}
+
+impl Format {
+    pub const fn new(codec: CodecKind, version: u16) -> Self {
+        Self { codec, version }
+    }
+
+    pub const fn bincode(version: u16) -> Self {
+        Self::new(CodecKind::Bincode, version)
+    }
+
+    pub const fn json(version: u16) -> Self {
+        Self::new(CodecKind::Json, version)
+    }
+
+    pub const fn msgpack(version: u16) -> Self {
+        Self::new(CodecKind::MsgPack, version)
+    }
+
+    pub fn header(&self) -> Header {
+        Header {
+            codec: self.codec,
+            version: self.version,
+        }
+    }
+}
+
+impl Default for Format {
+    fn default() -> Self {
+        Format::bincode(0)
+    }
+}
+
+/// Result of reading a stored value under a `Format`.
+#[derive(Debug)]
+pub enum Decoded<'a, T> {
+    Value(T),
+    /// Written under another schema version; the payload can be fed to a migration.
+    Outdated(Header, &'a [u8]),
+    /// No recognizable header, e.g. a value written before headers existed.
+    Unrecognized,
+}
+
+pub fn encode<T>(format: Format, value: &T) -> anyhow::Result<Vec<u8>>
+    where
+        T: Serialize + ?Sized,
+{
+    let mut bytes = format.header().to_bytes().to_vec();
+    bytes.extend(format.codec.encode(value)?);
+    Ok(bytes)
+}
+
+/// Decode with the codec named in the header. A version mismatch is `Outdated`,
+/// a payload that doesn't decode under a matching version is an error.
+pub fn decode<T>(format: Format, bytes: &[u8]) -> anyhow::Result<Decoded<'_, T>>
+    where
+        T: DeserializeOwned,
+{
+    match Header::split(bytes) {
+        None => Ok(Decoded::Unrecognized),
+        Some((header, payload)) if header.version != format.version => Ok(Decoded::Outdated(header, payload)),
+        Some((header, payload)) => header
+            .codec
+            .decode(payload)
+            .map(Decoded::Value)
+            .map_err(|e| anyhow!("decode {:?} v{} err: {}", header.codec, header.version, e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct V1 {
+        symbol: String,
+        price: u64,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct V2 {
+        symbol: String,
+        price: u64,
+        updated: u64,
+    }
+
+    #[test]
+    fn test_codecs_round_trip() {
+        let v = V1 { symbol: "BTCUSDT".into(), price: 42 };
+        for codec in [CodecKind::Bincode, CodecKind::Json, CodecKind::MsgPack] {
+            let bytes = encode(Format::new(codec, 1), &v).unwrap();
+            assert_eq!(bytes[..2], [MAGIC, codec.id()]);
+            match decode::<V1>(Format::new(codec, 1), &bytes).unwrap() {
+                Decoded::Value(d) => assert_eq!(d, v),
+                d => panic!("{:?}", d),
+            }
+        }
+
+        let json = encode(Format::json(1), &v).unwrap();
+        assert_eq!(&json[HEADER_LEN..], br#"{"symbol":"BTCUSDT","price":42}"#);
+    }
+
+    #[test]
+    fn test_reader_follows_header_codec() {
+        let v = V1 { symbol: "ETHUSDC".into(), price: 7 };
+        let bytes = encode(Format::msgpack(1), &v).unwrap();
+        assert!(matches!(decode::<V1>(Format::bincode(1), &bytes).unwrap(), Decoded::Value(d) if d == v));
+    }
+
+    #[test]
+    fn test_outdated_and_unrecognized() {
+        let old = encode(Format::bincode(1), &V1 { symbol: "BNBBUSD".into(), price: 3 }).unwrap();
+        match decode::<V2>(Format::bincode(2), &old).unwrap() {
+            Decoded::Outdated(header, payload) => {
+                assert_eq!(header, Format::bincode(1).header());
+                let v1: V1 = header.codec.decode(payload).unwrap();
+                assert_eq!(v1.price, 3);
+            }
+            d => panic!("{:?}", d),
+        }
+
+        let legacy = bincode::serialize(&V1 { symbol: "X".into(), price: 1 }).unwrap();
+        assert!(matches!(decode::<V1>(Format::default(), &legacy).unwrap(), Decoded::Unrecognized));
+
+        // same version, but the type gained a field without a version bump
+        assert!(decode::<V2>(Format::bincode(1), &old).is_err());
+    }
+}
@@ -1,8 +1,18 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+use crate::helpers::asset_graph::{AssetGraph, Hop};
+use crate::helpers::instrument::{InstrumentId, Venue};
+use crate::helpers::symbol_info::SymbolInfo;
+
+#[derive(Clone, Debug, Default, PartialEq, Deserialize, Serialize)]
 pub struct PriceInfo {
     pub base_asset: String,
     pub quote_asset: String,
@@ -10,7 +20,7 @@ pub struct PriceInfo {
     pub updated: u64,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct BookTicker {
     pub update_id: u64,
     pub symbol: String,
@@ -20,107 +30,935 @@ pub struct BookTicker {
     pub best_ask_qty: Decimal,
 }
 
-#[derive(Debug, Clone)]
+/// Change to one coin's symbols on one venue, e.g. listings and delistings between
+/// two exchange info snapshots.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SymbolDiff {
+    pub coin: String,
+    pub venue: Venue,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl SymbolDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+impl fmt::Display for SymbolDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} +[{}] -[{}]", self.venue, self.coin, self.added.join(","), self.removed.join(","))
+    }
+}
+
+/// Outcome of a compare-and-set book ticker write.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TickerWrite {
+    /// Stored. `skipped` counts the update ids between the previous ticker and this one.
+    Applied { skipped: u64 },
+    /// Not newer than the stored ticker, which is kept.
+    Rejected { current: u64 },
+}
+
+/// Book ticker sequence health of one instrument.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SequenceStats {
+    /// Writes not newer than the stored ticker.
+    pub rejected: u64,
+    /// Writes that skipped update ids, and how many they skipped in total.
+    pub gaps: u64,
+    pub skipped: u64,
+    /// Most update ids skipped by one write; a large jump means the stream lagged.
+    pub largest_gap: u64,
+    /// The last gap, as the update ids on either side of it.
+    pub last_gap: Option<(u64, u64)>,
+}
+
+/// The kinds of data `CoinSymbolCache` holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum Dataset {
+    CoinSymbols,
+    Symbols,
+    SymbolInfos,
+    BookTickers,
+}
+
+/// A cache entry and the unix millis it was received at.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct Stamped<T> {
+    pub data: T,
+    pub received: u64,
+}
+
+impl<T> Stamped<T> {
+    /// Stamped with the current time.
+    pub fn new(data: T) -> Self {
+        Self { data, received: now_millis() }
+    }
+
+    pub fn age(&self, now: u64) -> Duration {
+        Duration::from_millis(now.saturating_sub(self.received))
+    }
+}
+
+/// A value read together with how old it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Aged<T> {
+    pub value: T,
+    pub age: Duration,
+    /// Older than the dataset's `MaxAge`.
+    pub stale: bool,
+}
+
+impl<T> Aged<T> {
+    /// The value, unless it's stale.
+    pub fn fresh(self) -> Option<T> {
+        (!self.stale).then_some(self.value)
+    }
+}
+
+/// How long each dataset may go without an update before it's stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaxAge {
+    pub coin_symbols: Duration,
+    pub symbols: Duration,
+    pub symbol_infos: Duration,
+    pub book_tickers: Duration,
+}
+
+impl Default for MaxAge {
+    fn default() -> Self {
+        Self {
+            coin_symbols: Duration::from_secs(900),
+            symbols: Duration::from_secs(60),
+            symbol_infos: Duration::from_secs(600),
+            book_tickers: Duration::from_secs(60),
+        }
+    }
+}
+
+impl MaxAge {
+    pub fn of(&self, dataset: Dataset) -> Duration {
+        match dataset {
+            Dataset::CoinSymbols => self.coin_symbols,
+            Dataset::Symbols => self.symbols,
+            Dataset::SymbolInfos => self.symbol_infos,
+            Dataset::BookTickers => self.book_tickers,
+        }
+    }
+}
+
+/// What `CoinSymbolCache::sweep` does with stale entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StaleAction {
+    /// Count them and leave them; readers see `Aged::stale`.
+    Flag,
+    /// Clear stale prices to zero and drop stale book tickers and symbol infos.
+    /// The coin index is only ever flagged, the exchange info refresh owns it.
+    Evict,
+}
+
+/// Stale and evicted entries per dataset, from one sweep.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SweepReport {
+    pub stale: BTreeMap<Dataset, usize>,
+    pub evicted: BTreeMap<Dataset, usize>,
+}
+
+impl SweepReport {
+    pub fn is_empty(&self) -> bool {
+        self.stale.is_empty()
+    }
+}
+
+impl fmt::Display for SweepReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "stale {:?}, evicted {:?}", self.stale, self.evicted)
+    }
+}
+
+/// Market data of every tracked instrument, keyed by `InstrumentId` so one process
+/// can follow the same pair on several markets and exchanges.
+#[derive(Debug, Default)]
 pub struct CoinSymbolCache {
-    pub coin_symbols: DashMap<String, Vec<String>>,
-    pub symbols: DashMap<String, PriceInfo>,
-    pub book_tickers: DashMap<String, BookTicker>,
+    /// Base asset to the instruments trading it, on every venue.
+    pub coin_symbols: DashMap<String, Stamped<BTreeSet<InstrumentId>>>,
+    pub symbols: DashMap<InstrumentId, Stamped<PriceInfo>>,
+    pub symbol_infos: DashMap<InstrumentId, Stamped<SymbolInfo>>,
+    pub book_tickers: DashMap<InstrumentId, Stamped<BookTicker>>,
+    /// Bare symbol to the venues it's known on.
+    pub venues: DashMap<String, BTreeSet<Venue>>,
+    /// Entries restored from a snapshot and not yet refreshed live, with the unix
+    /// millis they were saved at. Keyed by coin for `CoinSymbols`, instrument otherwise.
+    pub restored: DashMap<(Dataset, String), u64>,
+    pub max_age: MaxAge,
+    /// Per instrument book ticker rejections and gaps, only for instruments that had any.
+    pub sequences: DashMap<InstrumentId, SequenceStats>,
+    /// Assets linked by the instruments that are trading, from `symbol_infos`.
+    graph: RwLock<AssetGraph>,
 }
 
 impl CoinSymbolCache {
     pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_max_age(max_age: MaxAge) -> Self {
         Self {
-            coin_symbols: Default::default(),
-            symbols: Default::default(),
-            book_tickers: Default::default(),
+            max_age,
+            ..Self::default()
         }
     }
 
-    pub fn set_coin_symbols<C>(&self, coin: C, symbol: String) -> anyhow::Result<()>
+    /// Returns false when the coin already had the instrument.
+    pub fn add_coin_symbol<C>(&self, coin: C, id: InstrumentId) -> bool
         where
-            C: Into<String>
+            C: Into<String>,
     {
-        self.coin_symbols
-            .entry(coin.into())
-            .and_modify(|symbols| {
-                symbols.push(symbol.clone());
-            })
-            .or_insert_with(|| {
-                let mut v = Vec::new();
-                v.push(symbol.clone());
-                v
-            });
-        Ok(())
+        let mut ids = self.coin_symbols.entry(coin.into()).or_default();
+        ids.received = now_millis();
+        ids.data.insert(id)
+    }
+
+    /// Returns false when the coin didn't have the instrument. A coin left without
+    /// instruments is dropped.
+    pub fn remove_coin_symbol(&self, coin: &str, id: &InstrumentId) -> bool {
+        let removed = self.coin_symbols.get_mut(coin).is_some_and(|mut ids| ids.data.remove(id));
+        self.coin_symbols.remove_if(coin, |_, ids| ids.data.is_empty());
+        removed
     }
 
-    pub fn get_coin_symbols<C>(&self, coin: C) -> anyhow::Result<Option<Vec<String>>>
+    /// Make `symbols` the coin's whole set on `venue` in one step, so readers never
+    /// see a partly updated set, and return what changed. Other venues are untouched.
+    pub fn replace_coin_symbols<C, I>(&self, coin: C, venue: Venue, symbols: I) -> SymbolDiff
         where
-            C: Into<String>
+            C: Into<String>,
+            I: IntoIterator<Item=String>,
     {
-        Ok(self.coin_symbols.get(&coin.into()).map_or(Option::from(vec![]), |v| Option::from(v.value().clone())))
+        let coin = coin.into();
+        let next: BTreeSet<String> = symbols.into_iter().collect();
+        let diff = {
+            // the entry guard holds the shard lock until the swap is done
+            let mut ids = self.coin_symbols.entry(coin.clone()).or_default();
+            let current: BTreeSet<String> = ids
+                .data
+                .iter()
+                .filter(|id| id.venue() == venue)
+                .map(|id| id.symbol.clone())
+                .collect();
+            let diff = SymbolDiff {
+                coin: coin.clone(),
+                venue,
+                added: next.difference(&current).cloned().collect(),
+                removed: current.difference(&next).cloned().collect(),
+            };
+            self.mark_live(Dataset::CoinSymbols, &coin);
+            ids.received = now_millis();
+            ids.data.retain(|id| id.venue() != venue);
+            ids.data.extend(next.into_iter().map(|symbol| venue.instrument(symbol)));
+            diff
+        };
+        self.coin_symbols.remove_if(&coin, |_, ids| ids.data.is_empty());
+        diff
+    }
+
+    /// Replace `venue`'s part of the index with an exchange info snapshot of coin to
+    /// symbols. Coins missing from the snapshot lose all their symbols on that venue.
+    /// Only non-empty diffs are returned, sorted by coin.
+    pub fn sync_coin_symbols(&self, venue: Venue, snapshot: HashMap<String, BTreeSet<String>>) -> Vec<SymbolDiff> {
+        let gone: Vec<String> = self
+            .coin_symbols
+            .iter()
+            .filter(|entry| !snapshot.contains_key(entry.key()) && entry.data.iter().any(|id| id.venue() == venue))
+            .map(|entry| entry.key().clone())
+            .collect();
+
+        let mut diffs: Vec<SymbolDiff> = snapshot
+            .into_iter()
+            .map(|(coin, symbols)| self.replace_coin_symbols(coin, venue, symbols))
+            .chain(gone.into_iter().map(|coin| self.replace_coin_symbols(coin, venue, [])))
+            .filter(|diff| !diff.is_empty())
+            .collect();
+        diffs.sort_by(|a, b| a.coin.cmp(&b.coin));
+        diffs
     }
 
-    pub fn set_book_ticker<S>(&self, symbol: S, book_ticker: BookTicker) -> anyhow::Result<()>
+    /// The coin's instruments on every venue, `None` when it has none.
+    pub fn get_coin_symbols(&self, coin: &str) -> Option<BTreeSet<InstrumentId>> {
+        self.coin_symbols.get(coin).map(|v| v.data.clone())
+    }
+
+    /// The coin's symbols on one venue, `None` when it has none there.
+    pub fn get_coin_symbols_on(&self, coin: &str, venue: Venue) -> Option<BTreeSet<String>> {
+        let symbols: BTreeSet<String> = self
+            .coin_symbols
+            .get(coin)?
+            .data
+            .iter()
+            .filter(|id| id.venue() == venue)
+            .map(|id| id.symbol.clone())
+            .collect();
+        (!symbols.is_empty()).then_some(symbols)
+    }
+
+    /// Store the ticker only if its `update_id` is newer than the stored one, so a
+    /// second connection or a replay can't move the book backwards. Rejections and
+    /// skipped update ids are recorded in `sequences`. Update ids are the venue's
+    /// order book ids, which also move when levels below the top change, so small
+    /// gaps are normal; the size of the largest one is what signals a lagging stream.
+    /// An evicted ticker leaves no trace, the next write after eviction is always
+    /// applied.
+    pub fn set_book_ticker(&self, id: InstrumentId, book_ticker: BookTicker) -> TickerWrite {
+        let update_id = book_ticker.update_id;
+        let write = match self.book_tickers.entry(id.clone()) {
+            Entry::Occupied(mut entry) => {
+                let current = entry.get().data.update_id;
+                if update_id <= current {
+                    TickerWrite::Rejected { current }
+                } else {
+                    entry.insert(Stamped::new(book_ticker));
+                    TickerWrite::Applied { skipped: update_id - current - 1 }
+                }
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Stamped::new(book_ticker));
+                TickerWrite::Applied { skipped: 0 }
+            }
+        };
+
+        match write {
+            TickerWrite::Applied { skipped } => {
+                self.index(&id);
+                self.mark_live(Dataset::BookTickers, &id);
+                if skipped > 0 {
+                    let mut stats = self.sequences.entry(id).or_default();
+                    stats.gaps += 1;
+                    stats.skipped += skipped;
+                    stats.largest_gap = stats.largest_gap.max(skipped);
+                    stats.last_gap = Some((update_id - skipped - 1, update_id));
+                }
+            }
+            TickerWrite::Rejected { .. } => {
+                self.sequences.entry(id).or_default().rejected += 1;
+            }
+        }
+        write
+    }
+
+    /// Book ticker writes rejected as out of order, over all instruments.
+    pub fn rejected_book_tickers(&self) -> u64 {
+        self.sequences.iter().map(|stats| stats.rejected).sum()
+    }
+
+    /// Instruments whose book tickers had gaps or rejections, sorted.
+    pub fn sequence_stats(&self) -> Vec<(InstrumentId, SequenceStats)> {
+        let mut stats: Vec<_> = self.sequences.iter().map(|e| (e.key().clone(), *e.value())).collect();
+        stats.sort_by(|a, b| a.0.cmp(&b.0));
+        stats
+    }
+
+    /// `None` until the first book ticker for the instrument arrives.
+    pub fn get_book_ticker(&self, id: &InstrumentId) -> Option<BookTicker> {
+        self.book_tickers.get(id).map(|v| v.data.clone())
+    }
+
+    /// The book ticker and its age at `now` (unix millis).
+    pub fn get_book_ticker_aged(&self, id: &InstrumentId, now: u64) -> Option<Aged<BookTicker>> {
+        self.book_tickers.get(id).map(|v| self.aged(Dataset::BookTickers, &v, now))
+    }
+
+    pub fn set_symbols(&self, id: InstrumentId, price_info: PriceInfo) {
+        self.index(&id);
+        self.mark_live(Dataset::Symbols, &id);
+        self.symbols.insert(id, Stamped::new(price_info));
+    }
+
+    /// `None` for an unknown instrument. A known one without a price yet has `price` zero.
+    pub fn get_symbols(&self, id: &InstrumentId) -> Option<PriceInfo> {
+        self.symbols.get(id).map(|v| v.data.clone())
+    }
+
+    /// The price info and its age at `now` (unix millis).
+    pub fn get_symbols_aged(&self, id: &InstrumentId, now: u64) -> Option<Aged<PriceInfo>> {
+        self.symbols.get(id).map(|v| self.aged(Dataset::Symbols, &v, now))
+    }
+
+    /// Returns the info it replaced, if any.
+    pub fn set_symbol_info(&self, venue: Venue, info: SymbolInfo) -> Option<SymbolInfo> {
+        let id = venue.instrument(info.symbol.as_str());
+        self.index(&id);
+        self.mark_live(Dataset::SymbolInfos, &id);
+        self.link(&id, &info);
+        self.symbol_infos.insert(id, Stamped::new(info)).map(|previous| previous.data)
+    }
+
+    /// Forget a delisted instrument: its rules, price, book ticker and graph edge.
+    /// Returns false when it wasn't known.
+    pub fn remove_symbol(&self, id: &InstrumentId) -> bool {
+        let had_info = self.symbol_infos.remove(id).is_some();
+        let had_price = self.symbols.remove(id).is_some();
+        let had_ticker = self.book_tickers.remove(id).is_some();
+        self.sequences.remove(id);
+        self.graph_mut().remove(id);
+        for dataset in [Dataset::Symbols, Dataset::SymbolInfos, Dataset::BookTickers] {
+            self.mark_live(dataset, id);
+        }
+        if let Some(mut venues) = self.venues.get_mut(&id.symbol) {
+            venues.remove(&id.venue());
+        }
+        self.venues.remove_if(&id.symbol, |_, venues| venues.is_empty());
+        had_info || had_price || had_ticker
+    }
+
+    pub fn get_symbol_info(&self, id: &InstrumentId) -> Option<SymbolInfo> {
+        self.symbol_infos.get(id).map(|v| v.data.clone())
+    }
+
+    /// The trading rules and their age at `now` (unix millis).
+    pub fn get_symbol_info_aged(&self, id: &InstrumentId, now: u64) -> Option<Aged<SymbolInfo>> {
+        self.symbol_infos.get(id).map(|v| self.aged(Dataset::SymbolInfos, &v, now))
+    }
+
+    /// True only for a known instrument whose status is `TRADING`.
+    pub fn is_trading(&self, id: &InstrumentId) -> bool {
+        self.symbol_infos.get(id).is_some_and(|info| info.data.is_trading())
+    }
+
+    /// Known instruments that aren't `TRADING`, sorted.
+    pub fn non_trading_symbols(&self) -> Vec<InstrumentId> {
+        let mut ids: Vec<InstrumentId> = self
+            .symbol_infos
+            .iter()
+            .filter(|info| !info.data.is_trading())
+            .map(|info| info.key().clone())
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    /// Venues a bare symbol is known on, sorted.
+    pub fn venues_of(&self, symbol: &str) -> Vec<Venue> {
+        self.venues.get(symbol).map(|v| v.iter().copied().collect()).unwrap_or_default()
+    }
+
+    /// Price info of a bare symbol on every venue that has it.
+    pub fn find_symbols(&self, symbol: &str) -> Vec<(InstrumentId, PriceInfo)> {
+        self.venues_of(symbol)
+            .into_iter()
+            .map(|venue| venue.instrument(symbol))
+            .filter_map(|id| self.get_symbols(&id).map(|info| (id, info)))
+            .collect()
+    }
+
+    /// Book tickers of a bare symbol on every venue that has one.
+    pub fn find_book_tickers(&self, symbol: &str) -> Vec<(InstrumentId, BookTicker)> {
+        self.venues_of(symbol)
+            .into_iter()
+            .map(|venue| venue.instrument(symbol))
+            .filter_map(|id| self.get_book_ticker(&id).map(|ticker| (id, ticker)))
+            .collect()
+    }
+
+    /// True while any entry still comes from a snapshot rather than the exchange.
+    pub fn is_degraded(&self) -> bool {
+        !self.restored.is_empty()
+    }
+
+    pub fn is_restored<K>(&self, dataset: Dataset, key: &K) -> bool
         where
-            S: Into<String>,
+            K: ToString + ?Sized,
     {
-        self.book_tickers.insert(symbol.into(), book_ticker);
-        Ok(())
+        !self.restored.is_empty() && self.restored.contains_key(&(dataset, key.to_string()))
     }
 
-    pub fn get_book_ticker<S>(&self, symbol: S) -> anyhow::Result<Option<BookTicker>>
+    /// How old a restored entry is at `now` (unix millis), `None` once it was refreshed live.
+    pub fn restored_age<K>(&self, dataset: Dataset, key: &K, now: u64) -> Option<Duration>
         where
-            S: Into<String>,
+            K: ToString + ?Sized,
     {
-        Ok(self.book_tickers.get(&symbol.into()).map_or(Option::from(BookTicker {
-            update_id: 0,
-            symbol: "".to_string(),
-            best_bid: Default::default(),
-            best_bid_qty: Default::default(),
-            best_ask: Default::default(),
-            best_ask_qty: Default::default(),
-        }), |v| Option::from(v.value().clone())))
+        if self.restored.is_empty() {
+            return None;
+        }
+        let saved_at = *self.restored.get(&(dataset, key.to_string()))?;
+        Some(Duration::from_millis(now.saturating_sub(saved_at)))
+    }
+
+    /// Restored entries not yet refreshed, per dataset.
+    pub fn restored_counts(&self) -> BTreeMap<Dataset, usize> {
+        let mut counts = BTreeMap::new();
+        for entry in self.restored.iter() {
+            *counts.entry(entry.key().0).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Look for entries older than their dataset's `MaxAge` at `now` (unix millis) and
+    /// count them, evicting them too when `action` says so.
+    pub fn sweep(&self, now: u64, action: StaleAction) -> SweepReport {
+        let mut report = SweepReport::default();
+        let stale_coins = self.coin_symbols.iter().filter(|ids| ids.age(now) > self.max_age.coin_symbols).count();
+        if stale_coins > 0 {
+            report.stale.insert(Dataset::CoinSymbols, stale_coins);
+        }
+
+        let evict = action == StaleAction::Evict;
+        let mut count = |dataset: Dataset| {
+            *report.stale.entry(dataset).or_default() += 1;
+            if evict {
+                *report.evicted.entry(dataset).or_default() += 1;
+            }
+        };
+
+        // a zero price already means "no price", there's nothing stale to serve
+        let max = self.max_age.symbols;
+        for mut info in self.symbols.iter_mut().filter(|info| !info.data.price.is_zero() && info.age(now) > max) {
+            if evict {
+                info.data.price = Decimal::ZERO;
+                self.mark_live(Dataset::Symbols, info.key());
+            }
+            count(Dataset::Symbols);
+        }
+
+        let max = self.max_age.symbol_infos;
+        self.symbol_infos.retain(|id, info| {
+            let stale = info.age(now) > max;
+            if stale {
+                count(Dataset::SymbolInfos);
+                if evict {
+                    self.mark_live(Dataset::SymbolInfos, id);
+                    self.graph_mut().remove(id);
+                }
+            }
+            !(stale && evict)
+        });
+
+        let max = self.max_age.book_tickers;
+        self.book_tickers.retain(|id, ticker| {
+            let stale = ticker.age(now) > max;
+            if stale {
+                count(Dataset::BookTickers);
+                if evict {
+                    self.mark_live(Dataset::BookTickers, id);
+                }
+            }
+            !(stale && evict)
+        });
+        report
+    }
+
+    /// The asset graph, read-locked. Hold it briefly, symbol info writes wait on it.
+    pub fn graph(&self) -> RwLockReadGuard<'_, AssetGraph> {
+        self.graph.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Trading instruments that turn `from` straight into `to`.
+    pub fn direct_markets(&self, from: &str, to: &str) -> Vec<Hop> {
+        self.graph().direct(from, to)
+    }
+
+    /// Assets `base` trades against as the base asset.
+    pub fn quotes_for(&self, base: &str) -> Vec<String> {
+        self.graph().quotes_for(base)
     }
 
-    pub fn set_symbols<S>(&self, symbol: S, price_info: PriceInfo) -> anyhow::Result<()>
+    /// Trading instruments quoted in `quote`.
+    pub fn markets_quoted_in(&self, quote: &str) -> BTreeSet<InstrumentId> {
+        self.graph().quoted_in(quote)
+    }
+
+    /// Conversion paths from `from` to `to` of at most `max_hops` trades, see `AssetGraph::paths`.
+    pub fn paths(&self, from: &str, to: &str, max_hops: usize) -> Vec<Vec<Hop>> {
+        self.graph().paths(from, to, max_hops)
+    }
+
+    /// Conversion paths that only go through the `via` assets, see `AssetGraph::paths_via`.
+    pub fn paths_via(&self, from: &str, to: &str, max_hops: usize, via: &BTreeSet<String>) -> Vec<Vec<Hop>> {
+        self.graph().paths_via(from, to, max_hops, via)
+    }
+
+    /// Keep the graph edge in line with the instrument's status: trading instruments
+    /// are linked, halted ones aren't.
+    pub(crate) fn link(&self, id: &InstrumentId, info: &SymbolInfo) {
+        let trading = info.is_trading();
+        if !trading && !self.graph().contains(id) {
+            return;
+        }
+        let mut graph = self.graph_mut();
+        if trading {
+            graph.insert(id.clone(), info.base_asset.as_str(), info.quote_asset.as_str());
+        } else {
+            graph.remove(id);
+        }
+    }
+
+    fn graph_mut(&self) -> RwLockWriteGuard<'_, AssetGraph> {
+        self.graph.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn aged<T>(&self, dataset: Dataset, stamped: &Stamped<T>, now: u64) -> Aged<T>
         where
-            S: Into<String>
+            T: Clone,
     {
-        self.symbols.insert(symbol.into(), price_info);
-        Ok(())
+        let age = stamped.age(now);
+        Aged {
+            value: stamped.data.clone(),
+            age,
+            stale: age > self.max_age.of(dataset),
+        }
     }
 
-    pub fn get_symbols<S>(&self, symbol: S) -> anyhow::Result<Option<PriceInfo>>
+    fn mark_live<K>(&self, dataset: Dataset, key: &K)
         where
-            S: Into<String>
+            K: ToString + ?Sized,
     {
-        Ok(self.symbols.get(&symbol.into()).map_or(Option::from(PriceInfo::default()), |v| Option::from(v.value().clone())))
+        // skip the key allocation on the hot path once everything is live
+        if !self.restored.is_empty() {
+            self.restored.remove(&(dataset, key.to_string()));
+        }
+    }
+
+    pub(crate) fn index(&self, id: &InstrumentId) {
+        if !self.venues.get(&id.symbol).is_some_and(|venues| venues.contains(&id.venue())) {
+            self.venues.entry(id.symbol.clone()).or_default().insert(id.venue());
+        }
     }
 }
 
+pub fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::conf::vars::{Market, Platform};
+
+    const SPOT: Venue = Venue::new(Platform::Binance, Market::Spot);
+    const FUTURES: Venue = Venue::new(Platform::Binance, Market::Futures);
+    const HUOBI: Venue = Venue::new(Platform::Huobi, Market::Spot);
+
+    fn price_info(price: Decimal) -> PriceInfo {
+        PriceInfo {
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            price,
+            updated: 0,
+        }
+    }
+
+    fn set(symbols: &[&str]) -> BTreeSet<String> {
+        symbols.iter().map(|s| s.to_string()).collect()
+    }
 
     #[test]
     fn test_coin_symbols_cache() {
         println!("----- test_coin_symbols cache");
         let cache = CoinSymbolCache::new();
-        let result = cache.set_coin_symbols("BTC".to_string(), "BTCUDST".to_string());
-        println!("coin symbols result: {:?}", result);
-        let result = cache.set_coin_symbols("BTC".to_string(), "BTCBUSD".to_string());
-        println!("coin symbols result: {:?}", result);
-        let result = cache.get_coin_symbols("BTC".to_string());
-        println!("coin symbols result: {:?}", result);
-
-        let result = cache.set_symbols("BTCUSDT".to_string(), PriceInfo {
-            base_asset: "BTC".to_string(),
-            quote_asset: "USDT".to_string(),
-            price: Default::default(),
-            updated: 0,
+        assert_eq!(cache.get_coin_symbols("BTC"), None);
+
+        assert!(cache.add_coin_symbol("BTC", SPOT.instrument("BTCUSDT")));
+        assert!(cache.add_coin_symbol("BTC", SPOT.instrument("BTCBUSD")));
+        assert!(cache.add_coin_symbol("BTC", FUTURES.instrument("BTCUSDT")));
+        assert!(!cache.add_coin_symbol("BTC", SPOT.instrument("BTCUSDT")));
+        assert_eq!(cache.get_coin_symbols("BTC").unwrap().len(), 3);
+        assert_eq!(cache.get_coin_symbols_on("BTC", SPOT).unwrap(), set(&["BTCBUSD", "BTCUSDT"]));
+
+        assert!(cache.remove_coin_symbol("BTC", &SPOT.instrument("BTCBUSD")));
+        assert!(!cache.remove_coin_symbol("BTC", &SPOT.instrument("BTCBUSD")));
+        assert!(!cache.remove_coin_symbol("ETH", &SPOT.instrument("ETHUSDT")));
+        assert!(cache.remove_coin_symbol("BTC", &SPOT.instrument("BTCUSDT")));
+        assert_eq!(cache.get_coin_symbols_on("BTC", SPOT), None);
+        assert!(cache.remove_coin_symbol("BTC", &FUTURES.instrument("BTCUSDT")));
+        assert_eq!(cache.get_coin_symbols("BTC"), None);
+    }
+
+    #[test]
+    fn test_replace_coin_symbols_diff() {
+        let cache = CoinSymbolCache::new();
+        let diff = cache.replace_coin_symbols("BTC", SPOT, set(&["BTCUSDT", "BTCBUSD"]));
+        assert_eq!(diff.added, vec!["BTCBUSD", "BTCUSDT"]);
+        assert!(diff.removed.is_empty());
+        cache.replace_coin_symbols("BTC", FUTURES, set(&["BTCUSDT"]));
+
+        let diff = cache.replace_coin_symbols("BTC", SPOT, set(&["BTCUSDT", "BTCUSDC"]));
+        assert_eq!(diff.to_string(), "binance:spot BTC +[BTCUSDC] -[BTCBUSD]");
+        assert_eq!(cache.get_coin_symbols_on("BTC", SPOT).unwrap(), set(&["BTCUSDC", "BTCUSDT"]));
+        assert_eq!(cache.get_coin_symbols_on("BTC", FUTURES).unwrap(), set(&["BTCUSDT"]));
+
+        assert!(cache.replace_coin_symbols("BTC", SPOT, set(&["BTCUSDC", "BTCUSDT"])).is_empty());
+    }
+
+    #[test]
+    fn test_sync_coin_symbols() {
+        let cache = CoinSymbolCache::new();
+        cache.add_coin_symbol("BTC", SPOT.instrument("BTCUSDT"));
+        cache.add_coin_symbol("LUNA", SPOT.instrument("LUNAUSDT"));
+        cache.add_coin_symbol("LUNA", HUOBI.instrument("LUNAUSDT"));
+
+        let snapshot = HashMap::from([
+            ("BTC".to_string(), set(&["BTCUSDT"])),
+            ("ETH".to_string(), set(&["ETHUSDT", "ETHBTC"])),
+        ]);
+        let diffs = cache.sync_coin_symbols(SPOT, snapshot);
+        assert_eq!(diffs, vec![
+            SymbolDiff { coin: "ETH".into(), venue: SPOT, added: vec!["ETHBTC".into(), "ETHUSDT".into()], removed: vec![] },
+            SymbolDiff { coin: "LUNA".into(), venue: SPOT, added: vec![], removed: vec!["LUNAUSDT".into()] },
+        ]);
+        // delisted on one exchange, still listed on the other
+        assert_eq!(cache.get_coin_symbols("LUNA").unwrap(), BTreeSet::from([HUOBI.instrument("LUNAUSDT")]));
+        assert_eq!(cache.coin_symbols.len(), 3);
+    }
+
+    #[test]
+    fn test_symbols_miss_is_none() {
+        let cache = CoinSymbolCache::new();
+        let btc = SPOT.instrument("BTCUSDT");
+        assert!(cache.get_symbols(&btc).is_none());
+
+        // a listed symbol with no trade yet is distinguishable from an unknown one
+        cache.set_symbols(btc.clone(), price_info(Decimal::ZERO));
+        assert_eq!(cache.get_symbols(&btc).unwrap().price, Decimal::ZERO);
+
+        cache.set_symbols(btc.clone(), price_info(Decimal::new(42, 0)));
+        assert_eq!(cache.get_symbols(&btc).unwrap().price, Decimal::new(42, 0));
+        assert!(cache.get_symbols(&SPOT.instrument("ETHUSDT")).is_none());
+    }
+
+    #[test]
+    fn test_same_symbol_on_several_venues() {
+        let cache = CoinSymbolCache::new();
+        cache.set_symbols(SPOT.instrument("BTCUSDT"), price_info(Decimal::new(100, 0)));
+        cache.set_symbols(FUTURES.instrument("BTCUSDT"), price_info(Decimal::new(101, 0)));
+        cache.set_symbols(HUOBI.instrument("BTCUSDT"), price_info(Decimal::new(99, 0)));
+
+        assert_eq!(cache.get_symbols(&SPOT.instrument("BTCUSDT")).unwrap().price, Decimal::new(100, 0));
+        assert_eq!(cache.get_symbols(&FUTURES.instrument("BTCUSDT")).unwrap().price, Decimal::new(101, 0));
+        assert_eq!(cache.venues_of("BTCUSDT"), vec![SPOT, FUTURES, HUOBI]);
+
+        let prices: Vec<(Venue, Decimal)> = cache
+            .find_symbols("BTCUSDT")
+            .into_iter()
+            .map(|(id, info)| (id.venue(), info.price))
+            .collect();
+        assert_eq!(prices, vec![(SPOT, Decimal::new(100, 0)), (FUTURES, Decimal::new(101, 0)), (HUOBI, Decimal::new(99, 0))]);
+        assert!(cache.find_symbols("ETHUSDT").is_empty());
+        assert!(cache.find_book_tickers("BTCUSDT").is_empty());
+    }
+
+    #[test]
+    fn test_symbol_info_status() {
+        let cache = CoinSymbolCache::new();
+        let info = |symbol: &str, status: &str| SymbolInfo {
+            symbol: symbol.to_string(),
+            status: status.to_string(),
+            ..Default::default()
+        };
+        assert!(cache.set_symbol_info(SPOT, info("BTCUSDT", "TRADING")).is_none());
+        cache.set_symbol_info(SPOT, info("LUNAUSDT", "BREAK"));
+        cache.set_symbol_info(SPOT, info("ETHUSDT", "HALT"));
+        cache.set_symbol_info(FUTURES, info("ETHUSDT", "TRADING"));
+
+        assert!(cache.is_trading(&SPOT.instrument("BTCUSDT")));
+        assert!(!cache.is_trading(&SPOT.instrument("LUNAUSDT")));
+        assert!(!cache.is_trading(&SPOT.instrument("UNKNOWN")));
+        assert!(cache.is_trading(&FUTURES.instrument("ETHUSDT")));
+        assert_eq!(cache.non_trading_symbols(), vec![SPOT.instrument("ETHUSDT"), SPOT.instrument("LUNAUSDT")]);
+
+        let previous = cache.set_symbol_info(SPOT, info("ETHUSDT", "TRADING")).unwrap();
+        assert_eq!(previous.status, "HALT");
+        assert_eq!(cache.get_symbol_info(&SPOT.instrument("ETHUSDT")).unwrap().status, "TRADING");
+    }
+
+    #[test]
+    fn test_aged_getters() {
+        let cache = CoinSymbolCache::with_max_age(MaxAge { symbols: Duration::from_secs(5), ..Default::default() });
+        let btc = SPOT.instrument("BTCUSDT");
+        assert!(cache.get_symbols_aged(&btc, now_millis()).is_none());
+
+        cache.set_symbols(btc.clone(), price_info(Decimal::new(42, 0)));
+        let received = cache.symbols.get(&btc).unwrap().received;
+        let aged = cache.get_symbols_aged(&btc, received + 5_000).unwrap();
+        assert_eq!(aged.age, Duration::from_secs(5));
+        assert!(!aged.stale);
+        assert_eq!(aged.fresh().unwrap().price, Decimal::new(42, 0));
+
+        let aged = cache.get_symbols_aged(&btc, received + 5_001).unwrap();
+        assert!(aged.stale);
+        assert!(aged.fresh().is_none());
+        // the other datasets keep their own limits
+        cache.set_symbol_info(SPOT, SymbolInfo { symbol: "BTCUSDT".to_string(), ..Default::default() });
+        assert!(!cache.get_symbol_info_aged(&btc, received + 60_000).unwrap().stale);
+    }
+
+    #[test]
+    fn test_sweep_flags_or_evicts_stale_entries() {
+        let cache = CoinSymbolCache::new();
+        let btc = SPOT.instrument("BTCUSDT");
+        let eth = SPOT.instrument("ETHUSDT");
+        cache.add_coin_symbol("BTC", btc.clone());
+        cache.set_symbols(btc.clone(), price_info(Decimal::new(42, 0)));
+        // no price yet, nothing to go stale
+        cache.set_symbols(eth.clone(), price_info(Decimal::ZERO));
+        cache.set_symbol_info(SPOT, SymbolInfo { symbol: "BTCUSDT".to_string(), ..Default::default() });
+        cache.set_book_ticker(btc.clone(), BookTicker {
+            update_id: 1,
+            symbol: "BTCUSDT".to_string(),
+            best_bid: Decimal::ONE,
+            best_bid_qty: Decimal::ONE,
+            best_ask: Decimal::TWO,
+            best_ask_qty: Decimal::ONE,
+        });
+
+        let now = now_millis();
+        assert!(cache.sweep(now, StaleAction::Evict).is_empty());
+
+        // past the price and book ticker limits, within the others
+        let later = now + 61_000;
+        let report = cache.sweep(later, StaleAction::Flag);
+        assert_eq!(report.stale, BTreeMap::from([(Dataset::Symbols, 1), (Dataset::BookTickers, 1)]));
+        assert!(report.evicted.is_empty());
+        assert_eq!(cache.get_symbols(&btc).unwrap().price, Decimal::new(42, 0));
+
+        let report = cache.sweep(later, StaleAction::Evict);
+        assert_eq!(report.evicted, report.stale);
+        assert_eq!(cache.get_symbols(&btc).unwrap().price, Decimal::ZERO);
+        assert!(cache.get_book_ticker(&btc).is_none());
+        assert!(cache.get_symbol_info(&btc).is_some());
+        assert!(cache.sweep(later, StaleAction::Evict).is_empty());
+
+        let report = cache.sweep(now + 901_000, StaleAction::Evict);
+        assert_eq!(report.to_string(), "stale {CoinSymbols: 1, SymbolInfos: 1}, evicted {SymbolInfos: 1}");
+        assert!(cache.get_coin_symbols("BTC").is_some());
+    }
+
+    #[test]
+    fn test_asset_graph_follows_listings() {
+        let cache = CoinSymbolCache::new();
+        let info = |symbol: &str, base: &str, quote: &str, status: &str| SymbolInfo {
+            symbol: symbol.to_string(),
+            base_asset: base.to_string(),
+            quote_asset: quote.to_string(),
+            status: status.to_string(),
+            ..Default::default()
+        };
+        cache.set_symbol_info(SPOT, info("BTCUSDC", "BTC", "USDC", "TRADING"));
+        cache.set_symbol_info(SPOT, info("ETHUSDC", "ETH", "USDC", "TRADING"));
+        cache.set_symbol_info(SPOT, info("ETHBTC", "ETH", "BTC", "TRADING"));
+        cache.set_symbol_info(SPOT, info("LUNAUSDC", "LUNA", "USDC", "BREAK"));
+        cache.set_symbol_info(FUTURES, info("BTCUSDC", "BTC", "USDC", "TRADING"));
+
+        let usdc: Vec<String> = cache.markets_quoted_in("USDC").iter().map(|id| id.to_string()).collect();
+        assert_eq!(usdc, vec!["binance:spot:BTCUSDC", "binance:spot:ETHUSDC", "binance:futures:BTCUSDC"]);
+        assert_eq!(cache.quotes_for("ETH"), vec!["BTC", "USDC"]);
+        assert_eq!(cache.direct_markets("USDC", "BTC").len(), 2);
+        assert_eq!(cache.paths("BTC", "ETH", 2).len(), 3);
+
+        // halted, then back
+        cache.set_symbol_info(SPOT, info("ETHBTC", "ETH", "BTC", "HALT"));
+        assert!(cache.direct_markets("ETH", "BTC").is_empty());
+        cache.set_symbol_info(SPOT, info("ETHBTC", "ETH", "BTC", "TRADING"));
+        assert_eq!(cache.direct_markets("ETH", "BTC").len(), 1);
+
+        // delisted
+        assert!(cache.remove_symbol(&SPOT.instrument("ETHUSDC")));
+        assert!(!cache.remove_symbol(&SPOT.instrument("ETHUSDC")));
+        assert_eq!(cache.quotes_for("ETH"), vec!["BTC"]);
+        assert!(cache.get_symbol_info(&SPOT.instrument("ETHUSDC")).is_none());
+        assert!(cache.venues_of("ETHUSDC").is_empty());
+    }
+
+    fn ticker(update_id: u64, bid: i64) -> BookTicker {
+        BookTicker {
+            update_id,
+            symbol: "BTCUSDT".to_string(),
+            best_bid: Decimal::new(bid, 0),
+            best_bid_qty: Decimal::ONE,
+            best_ask: Decimal::new(bid + 1, 0),
+            best_ask_qty: Decimal::ONE,
+        }
+    }
+
+    #[test]
+    fn test_book_ticker_rejects_out_of_order_updates() {
+        let cache = CoinSymbolCache::new();
+        let btc = SPOT.instrument("BTCUSDT");
+        let eth = SPOT.instrument("ETHUSDT");
+
+        // two connections delivering the same streams, interleaved and each lagging at times
+        let updates = [
+            (&btc, 1), (&btc, 2), (&eth, 10), (&btc, 1), (&btc, 3), (&btc, 2),
+            (&eth, 12), (&btc, 6), (&eth, 11), (&btc, 5), (&btc, 7), (&eth, 12), (&btc, 6),
+        ];
+        let writes: Vec<TickerWrite> = updates
+            .iter()
+            .map(|(id, update_id)| cache.set_book_ticker((*id).clone(), ticker(*update_id, *update_id as i64)))
+            .collect();
+
+        assert_eq!(writes[..5], [
+            TickerWrite::Applied { skipped: 0 },
+            TickerWrite::Applied { skipped: 0 },
+            TickerWrite::Applied { skipped: 0 },
+            TickerWrite::Rejected { current: 2 },
+            TickerWrite::Applied { skipped: 0 },
+        ]);
+        // ids aren't contiguous, a jump is applied like any newer update
+        assert_eq!(writes[7], TickerWrite::Applied { skipped: 2 });
+        assert_eq!(writes[9], TickerWrite::Rejected { current: 6 });
+
+        // the newest update wins whatever the arrival order
+        assert_eq!(cache.get_book_ticker(&btc).unwrap().update_id, 7);
+        assert_eq!(cache.get_book_ticker(&btc).unwrap().best_bid, Decimal::new(7, 0));
+        assert_eq!(cache.get_book_ticker(&eth).unwrap().update_id, 12);
+
+        assert_eq!(cache.rejected_book_tickers(), 6);
+        assert_eq!(cache.sequence_stats(), vec![
+            (btc, SequenceStats { rejected: 4, gaps: 1, skipped: 2, largest_gap: 2, last_gap: Some((3, 6)) }),
+            (eth, SequenceStats { rejected: 2, gaps: 1, skipped: 1, largest_gap: 1, last_gap: Some((10, 12)) }),
+        ]);
+    }
+
+    #[test]
+    fn test_book_ticker_concurrent_writers_keep_the_newest() {
+        let cache = std::sync::Arc::new(CoinSymbolCache::new());
+        let btc = SPOT.instrument("BTCUSDT");
+        let writers: Vec<_> = (0..4u64)
+            .map(|w| {
+                let cache = cache.clone();
+                let btc = btc.clone();
+                std::thread::spawn(move || {
+                    // each writer sees every update id, from a different starting point
+                    for i in 0..1_000u64 {
+                        let update_id = (i + w * 250) % 1_000 + 1;
+                        cache.set_book_ticker(btc.clone(), ticker(update_id, update_id as i64));
+                    }
+                })
+            })
+            .collect();
+        writers.into_iter().for_each(|w| w.join().unwrap());
+
+        let stored = cache.get_book_ticker(&btc).unwrap();
+        assert_eq!(stored.update_id, 1_000);
+        assert_eq!(stored.best_bid, Decimal::new(1_000, 0));
+    }
+
+    #[test]
+    fn test_book_ticker_miss_is_none() {
+        let cache = CoinSymbolCache::new();
+        let btc = SPOT.instrument("BTCUSDT");
+        assert!(cache.get_book_ticker(&btc).is_none());
+
+        cache.set_book_ticker(btc.clone(), BookTicker {
+            update_id: 7,
+            symbol: "BTCUSDT".to_string(),
+            best_bid: Decimal::new(1, 0),
+            best_bid_qty: Decimal::ZERO,
+            best_ask: Decimal::new(2, 0),
+            best_ask_qty: Decimal::ZERO,
         });
-        println!("symbols result: {:?}", result);
-        let result = cache.get_symbols("BTCUSDT".to_string());
-        println!("symbols result: {:?}", result);
+        let ticker = cache.get_book_ticker(&btc).unwrap();
+        assert_eq!(ticker.update_id, 7);
+        assert_eq!(ticker.best_ask, Decimal::new(2, 0));
+        assert!(cache.get_book_ticker(&FUTURES.instrument("BTCUSDT")).is_none());
     }
-}
\ No newline at end of file
+}
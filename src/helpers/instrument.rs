@@ -0,0 +1,95 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::conf::vars::{Market, Platform};
+
+/// Where an instrument trades: one market of one exchange.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct Venue {
+    pub platform: Platform,
+    pub market: Market,
+}
+
+impl Venue {
+    pub const fn new(platform: Platform, market: Market) -> Self {
+        Self { platform, market }
+    }
+
+    pub fn instrument<S>(&self, symbol: S) -> InstrumentId
+        where
+            S: Into<String>,
+    {
+        InstrumentId::new(self.platform, self.market, symbol)
+    }
+}
+
+impl fmt::Display for Venue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.platform, self.market)
+    }
+}
+
+/// A symbol qualified by venue, so spot and futures BTCUSDT, or the same pair on
+/// two exchanges, are different instruments. Written `platform:market:symbol`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Deserialize, Serialize)]
+pub struct InstrumentId {
+    pub platform: Platform,
+    pub market: Market,
+    pub symbol: String,
+}
+
+impl InstrumentId {
+    pub fn new<S>(platform: Platform, market: Market, symbol: S) -> Self
+        where
+            S: Into<String>,
+    {
+        Self {
+            platform,
+            market,
+            symbol: symbol.into(),
+        }
+    }
+
+    pub fn venue(&self) -> Venue {
+        Venue::new(self.platform, self.market)
+    }
+}
+
+impl fmt::Display for InstrumentId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.platform, self.market, self.symbol)
+    }
+}
+
+impl FromStr for InstrumentId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(platform), Some(market), Some(symbol)) if !symbol.is_empty() => {
+                Ok(InstrumentId::new(platform.parse()?, market.parse()?, symbol))
+            }
+            _ => Err(anyhow::anyhow!("instrument {:?} is not platform:market:symbol", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instrument_id_round_trip() {
+        let id = InstrumentId::new(Platform::Binance, Market::Futures, "BTCUSDT");
+        assert_eq!(id.to_string(), "binance:futures:BTCUSDT");
+        assert_eq!("binance:futures:BTCUSDT".parse::<InstrumentId>().unwrap(), id);
+        assert_eq!(id.venue().instrument("ETHUSDT").symbol, "ETHUSDT");
+
+        assert_ne!(id, InstrumentId::new(Platform::Binance, Market::Spot, "BTCUSDT"));
+        assert!("binance:spot".parse::<InstrumentId>().is_err());
+        assert!("kraken:spot:BTCUSDT".parse::<InstrumentId>().is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Redis key schema.
+//!
+//! Every key is `<namespace>:v<version>:<kind>:<part>...`, so two deployments can
+//! share one Redis under different namespaces, and a layout change bumps
+//! `SCHEMA_VERSION` instead of reinterpreting old keys. Parts are escaped, so a
+//! symbol containing `:` can't make one kind's key look like another's.
+
+use once_cell::sync::OnceCell;
+
+use crate::conf::config::Conf;
+use crate::conf::vars::{Market, Platform};
+
+// bump when the layout of any key kind changes
+pub const SCHEMA_VERSION: u32 = 2;
+// namespace used when `redis.namespace` is not configured
+pub const DEFAULT_NAMESPACE: &str = "ex";
+
+const SEPARATOR: char = ':';
+
+static KEYSPACE: OnceCell<KeySpace> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyKind {
+    /// Hash of symbols trading a base asset.
+    CoinSymbols,
+    /// Last price of one instrument.
+    Price,
+    /// Best bid/ask of one instrument.
+    BookTicker,
+    /// Exchange info snapshot of one venue.
+    ExchangeInfo,
+    /// Cross-process lock guarding the creation of another key.
+    Lock,
+}
+
+impl KeyKind {
+    pub const ALL: &'static [KeyKind] = &[
+        KeyKind::CoinSymbols,
+        KeyKind::Price,
+        KeyKind::BookTicker,
+        KeyKind::ExchangeInfo,
+        KeyKind::Lock,
+    ];
+
+    pub const fn as_str(&self) -> &'static str {
+        match self {
+            KeyKind::CoinSymbols => "coin_symbols",
+            KeyKind::Price => "price",
+            KeyKind::BookTicker => "book_ticker",
+            KeyKind::ExchangeInfo => "exchange_info",
+            KeyKind::Lock => "lock",
+        }
+    }
+
+    /// Number of parts after the kind.
+    pub const fn arity(&self) -> usize {
+        match self {
+            KeyKind::CoinSymbols | KeyKind::Lock => 1,
+            KeyKind::Price | KeyKind::BookTicker => 3,
+            KeyKind::ExchangeInfo => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeySpace {
+    namespace: String,
+    version: u32,
+}
+
+impl KeySpace {
+    pub fn new<N>(namespace: N) -> Self
+        where
+            N: Into<String>,
+    {
+        Self::with_version(namespace, SCHEMA_VERSION)
+    }
+
+    /// A keyspace for an older or newer layout, e.g. to read keys while migrating.
+    pub fn with_version<N>(namespace: N, version: u32) -> Self
+        where
+            N: Into<String>,
+    {
+        Self {
+            namespace: escape(&namespace.into()),
+            version,
+        }
+    }
+
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    /// `<namespace>:v<version>:` — every key of this keyspace starts with it.
+    pub fn prefix(&self) -> String {
+        format!("{}{}v{}{}", self.namespace, SEPARATOR, self.version, SEPARATOR)
+    }
+
+    /// Glob matching every key of one kind, for `SCAN MATCH`.
+    pub fn pattern(&self, kind: KeyKind) -> String {
+        format!("{}{}{}*", self.prefix(), kind.as_str(), SEPARATOR)
+    }
+
+    pub fn key(&self, kind: KeyKind, parts: &[&str]) -> String {
+        debug_assert_eq!(parts.len(), kind.arity(), "wrong part count for {}", kind.as_str());
+        let mut key = self.prefix();
+        key.push_str(kind.as_str());
+        for part in parts {
+            key.push(SEPARATOR);
+            key.push_str(&escape(part));
+        }
+        key
+    }
+
+    /// Split a key of this keyspace back into its kind and unescaped parts.
+    pub fn parse(&self, key: &str) -> Option<(KeyKind, Vec<String>)> {
+        let rest = key.strip_prefix(&self.prefix())?;
+        let mut segments = rest.split(SEPARATOR);
+        let kind_str = segments.next()?;
+        let kind = *KeyKind::ALL.iter().find(|k| k.as_str() == kind_str)?;
+        let parts: Vec<String> = segments.map(unescape).collect();
+        if parts.len() != kind.arity() {
+            return None;
+        }
+        Some((kind, parts))
+    }
+
+    pub fn coin_symbols(&self, asset: &str) -> String {
+        self.key(KeyKind::CoinSymbols, &[asset])
+    }
+
+    pub fn price(&self, platform: Platform, market: Market, symbol: &str) -> String {
+        self.key(KeyKind::Price, &[platform.as_str(), market.as_str(), symbol])
+    }
+
+    pub fn book_ticker(&self, platform: Platform, market: Market, symbol: &str) -> String {
+        self.key(KeyKind::BookTicker, &[platform.as_str(), market.as_str(), symbol])
+    }
+
+    pub fn exchange_info(&self, platform: Platform, market: Market) -> String {
+        self.key(KeyKind::ExchangeInfo, &[platform.as_str(), market.as_str()])
+    }
+
+    /// Lock for creating `key`, which is escaped whole into a single part.
+    pub fn lock(&self, key: &str) -> String {
+        self.key(KeyKind::Lock, &[key])
+    }
+
+    /// Pub/sub channel name. Channels don't share Redis' key space, but get the
+    /// same prefix so deployments sharing a server don't hear each other.
+    pub fn channel(&self, name: &str) -> String {
+        format!("{}channel{}{}", self.prefix(), SEPARATOR, escape(name))
+    }
+}
+
+/// The process keyspace, from `redis.namespace` in the startup config.
+pub fn keyspace() -> &'static KeySpace {
+    KEYSPACE.get_or_init(|| {
+        // unit tests run without a config file
+        if cfg!(test) {
+            return KeySpace::new("test");
+        }
+        KeySpace::new(Conf::get().redis.namespace.as_str())
+    })
+}
+
+pub fn coin_symbols(asset: &str) -> String {
+    keyspace().coin_symbols(asset)
+}
+
+pub fn price(platform: Platform, market: Market, symbol: &str) -> String {
+    keyspace().price(platform, market, symbol)
+}
+
+pub fn book_ticker(platform: Platform, market: Market, symbol: &str) -> String {
+    keyspace().book_ticker(platform, market, symbol)
+}
+
+pub fn exchange_info(platform: Platform, market: Market) -> String {
+    keyspace().exchange_info(platform, market)
+}
+
+pub fn lock(key: &str) -> String {
+    keyspace().lock(key)
+}
+
+pub fn channel(name: &str) -> String {
+    keyspace().channel(name)
+}
+
+fn escape(part: &str) -> String {
+    part.replace('%', "%25").replace(SEPARATOR, "%3A")
+}
+
+fn unescape(part: &str) -> String {
+    part.replace("%3A", ":").replace("%25", "%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_key_layout() {
+        let ks = KeySpace::new("prod");
+        assert_eq!(ks.coin_symbols("BTC"), "prod:v2:coin_symbols:BTC");
+        assert_eq!(ks.price(Platform::Binance, Market::Spot, "BTCUSDT"), "prod:v2:price:binance:spot:BTCUSDT");
+        assert_eq!(ks.pattern(KeyKind::Price), "prod:v2:price:*");
+        assert_eq!(ks.exchange_info(Platform::Huobi, Market::Delivery), "prod:v2:exchange_info:huobi:delivery");
+        assert_eq!(ks.channel("near:symbols"), "prod:v2:channel:near%3Asymbols");
+        assert_eq!(ks.lock(&ks.coin_symbols("BTC")), "prod:v2:lock:prod%3Av2%3Acoin_symbols%3ABTC");
+    }
+
+    #[test]
+    fn test_no_key_kinds_collide() {
+        let spaces = [KeySpace::new("prod"), KeySpace::new("dev"), KeySpace::with_version("prod", 1)];
+        let parts = ["BTC", "BTCUSDT", "binance", "spot", "a:b", "a%3Ab", "", "price"];
+
+        let mut seen = HashSet::new();
+        for ks in &spaces {
+            for kind in KeyKind::ALL {
+                // every combination of parts for this kind
+                let mut combos: Vec<Vec<&str>> = vec![vec![]];
+                for _ in 0..kind.arity() {
+                    combos = combos
+                        .into_iter()
+                        .flat_map(|c| parts.iter().map(move |p| [c.clone(), vec![*p]].concat()))
+                        .collect();
+                }
+                for combo in combos {
+                    let key = ks.key(*kind, &combo);
+                    assert!(seen.insert(key.clone()), "duplicate key {}", key);
+
+                    let (parsed_kind, parsed_parts) = ks.parse(&key).unwrap();
+                    assert_eq!(parsed_kind, *kind);
+                    assert_eq!(parsed_parts, combo);
+                    for other in spaces.iter().filter(|o| *o != ks) {
+                        assert!(other.parse(&key).is_none(), "{} parses in {:?}", key, other);
+                    }
+                }
+            }
+        }
+    }
+}
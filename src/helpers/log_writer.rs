@@ -0,0 +1,82 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use tokio::sync::watch;
+use tracing_appender::non_blocking::{NonBlocking, WorkerGuard};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::conf::config::Conf;
+
+struct Inner {
+    writer: NonBlocking,
+    guard: Option<WorkerGuard>,
+    dir: PathBuf,
+    name: String,
+}
+
+/// A log file writer whose target file can be switched while the subscriber is running.
+#[derive(Clone)]
+pub struct ReloadableWriter {
+    inner: Arc<RwLock<Inner>>,
+}
+
+impl ReloadableWriter {
+    pub fn new<P>(dir: P, name: &str) -> Self
+        where
+            P: AsRef<Path>,
+    {
+        let (writer, guard) = open(dir.as_ref(), name);
+        Self {
+            inner: Arc::new(RwLock::new(Inner {
+                writer,
+                guard: Some(guard),
+                dir: dir.as_ref().to_path_buf(),
+                name: name.to_string(),
+            })),
+        }
+    }
+
+    /// Switch to a new file. The previous writer is flushed when its guard drops.
+    pub fn reopen<P>(&self, dir: P, name: &str)
+        where
+            P: AsRef<Path>,
+    {
+        let mut inner = self.inner.write().unwrap();
+        if inner.dir == dir.as_ref() && inner.name == name {
+            return;
+        }
+        let (writer, guard) = open(dir.as_ref(), name);
+        inner.writer = writer;
+        inner.guard = Some(guard);
+        inner.dir = dir.as_ref().to_path_buf();
+        inner.name = name.to_string();
+    }
+
+    /// Reopen the log file whenever a new config snapshot changes the log section.
+    pub fn follow(&self, mut rx: watch::Receiver<Arc<Conf>>) {
+        let this = self.clone();
+        tokio::spawn(async move {
+            while rx.changed().await.is_ok() {
+                let conf = rx.borrow().clone();
+                this.reopen(conf.log_dir(), &conf.log.name);
+            }
+        });
+    }
+
+    /// Flush and stop the background writer. Call before the process exits.
+    pub fn shutdown(&self) {
+        self.inner.write().unwrap().guard.take();
+    }
+}
+
+impl<'a> MakeWriter<'a> for ReloadableWriter {
+    type Writer = NonBlocking;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.inner.read().unwrap().writer.clone()
+    }
+}
+
+fn open(dir: &Path, name: &str) -> (NonBlocking, WorkerGuard) {
+    tracing_appender::non_blocking(tracing_appender::rolling::never(dir, name))
+}
@@ -1,5 +1,15 @@
+pub mod asset_graph;
+pub mod backend;
 pub mod cache;
+pub mod codec;
 pub mod coin_symbol;
+pub mod instrument;
+pub mod keys;
+pub mod log_writer;
+pub mod near_cache;
+pub mod pricing;
+pub mod snapshot;
+pub mod symbol_info;
 
 
 #[cfg(test)]
@@ -9,9 +19,8 @@ mod tests {
     #[tokio::test]
     async fn test_cache() {
         println!("----- test cache");
-        let _ = crate::db::init_db().await.unwrap();
-        let mut client = crate::db::get_redis_connection().await.unwrap();
-        let set_result = cache::set_ex(&mut client, "hello", &"word", 10 as usize).await;
+        let mut client = backend::MemoryBackend::new();
+        let set_result = cache::set_ex(&mut client, "hello", &"word", 10_usize).await;
         let get_result: String = cache::get(&mut client, "hello").await.unwrap();
         println!("{:?}, {:?}", set_result, get_result);
     }
@@ -0,0 +1,441 @@
+//! Two-tier cache for values read on every tick but rarely changed.
+//!
+//! A bounded in-process map with a per-entry TTL sits in front of
+//! `helpers::cache`. Writes go through to the backend and are announced on a
+//! pub/sub channel, so other instances drop their local copy instead of serving
+//! it until it expires.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::helpers::backend::{CacheBackend, Subscriber};
+use crate::helpers::cache::{self, Lookup, OnError};
+use crate::helpers::codec::Format;
+
+// invalidation messages are tiny and read by every instance, keep them readable
+const INVALIDATION_FORMAT: Format = Format::json(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Invalidation {
+    origin: String,
+    key: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TierStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl TierStats {
+    /// Share of lookups answered by this tier, 0 when there were none.
+    pub fn hit_ratio(&self) -> f64 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.hits as f64 / total as f64
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NearCacheStats {
+    pub local: TierStats,
+    /// Only lookups that missed locally reach the remote tier.
+    pub remote: TierStats,
+    pub evictions: u64,
+    pub invalidations: u64,
+}
+
+impl fmt::Display for NearCacheStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "local {}/{} ({:.1}%), remote {}/{} ({:.1}%), evictions {}, invalidations {}",
+            self.local.hits,
+            self.local.hits + self.local.misses,
+            self.local.hit_ratio() * 100.0,
+            self.remote.hits,
+            self.remote.hits + self.remote.misses,
+            self.remote.hit_ratio() * 100.0,
+            self.evictions,
+            self.invalidations,
+        )
+    }
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    local_hits: AtomicU64,
+    local_misses: AtomicU64,
+    remote_hits: AtomicU64,
+    remote_misses: AtomicU64,
+    evictions: AtomicU64,
+    invalidations: AtomicU64,
+}
+
+#[derive(Debug)]
+struct LocalEntry<T> {
+    value: T,
+    expires: Instant,
+    seq: u64,
+}
+
+/// Entries plus their insertion order; the oldest entry goes first when full.
+#[derive(Debug)]
+pub(crate) struct Local<T> {
+    entries: HashMap<String, LocalEntry<T>>,
+    order: VecDeque<(u64, String)>,
+    seq: u64,
+}
+
+impl<T> Local<T> {
+    pub(crate) fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            seq: 0,
+        }
+    }
+
+    /// The entry for `key` unless it expired by `now`; an expired one is dropped.
+    pub(crate) fn get(&mut self, key: &str, now: Instant) -> Option<&T> {
+        if self.entries.get(key).is_some_and(|e| e.expires <= now) {
+            self.entries.remove(key);
+        }
+        self.entries.get(key).map(|e| &e.value)
+    }
+
+    /// Insert and return how many entries were evicted to stay within `capacity`.
+    pub(crate) fn insert(&mut self, key: String, value: T, expires: Instant, capacity: usize) -> u64 {
+        self.seq += 1;
+        self.order.push_back((self.seq, key.clone()));
+        self.entries.insert(key, LocalEntry { value, expires, seq: self.seq });
+
+        let mut evicted = 0;
+        while self.entries.len() > capacity {
+            let (seq, key) = match self.order.pop_front() {
+                Some(oldest) => oldest,
+                None => break,
+            };
+            // skip order slots left behind by re-inserts and removals
+            if self.entries.get(&key).is_some_and(|e| e.seq == seq) {
+                self.entries.remove(&key);
+                evicted += 1;
+            }
+        }
+        if self.order.len() > capacity.saturating_mul(2).max(16) {
+            let entries = &self.entries;
+            self.order.retain(|(seq, key)| entries.get(key).is_some_and(|e| e.seq == *seq));
+        }
+        evicted
+    }
+}
+
+/// A bounded local TTL map in front of a `CacheBackend`. Share it through an `Arc`.
+#[derive(Debug)]
+pub struct NearCache<T> {
+    local: Mutex<Local<T>>,
+    capacity: usize,
+    ttl: Duration,
+    format: Format,
+    channel: String,
+    origin: String,
+    counters: Counters,
+}
+
+impl<T> NearCache<T>
+    where
+        T: Clone + Serialize + DeserializeOwned + Send + 'static,
+{
+    /// `ttl` bounds how long a local copy is served when an invalidation is missed;
+    /// `channel` is shared by every instance caching the same data, e.g. `keys::channel("symbol_info")`.
+    pub fn new<C>(capacity: usize, ttl: Duration, format: Format, channel: C) -> Self
+        where
+            C: Into<String>,
+    {
+        Self {
+            local: Mutex::new(Local::new()),
+            capacity: capacity.max(1),
+            ttl,
+            format,
+            channel: channel.into(),
+            origin: instance_id(),
+            counters: Counters::default(),
+        }
+    }
+
+    /// Local copy, then the backend. A value found remotely is kept locally.
+    pub async fn get<B>(&self, backend: &mut B, key: &str) -> anyhow::Result<Option<T>>
+        where
+            B: CacheBackend + ?Sized,
+    {
+        if let Some(value) = self.get_local(key) {
+            return Ok(Some(value));
+        }
+        match cache::lookup::<_, T>(backend, key, self.format).await {
+            Lookup::Hit(value) => {
+                self.counters.remote_hits.fetch_add(1, Ordering::Relaxed);
+                self.put_local(key, value.clone());
+                Ok(Some(value))
+            }
+            Lookup::Miss => {
+                self.counters.remote_misses.fetch_add(1, Ordering::Relaxed);
+                Ok(None)
+            }
+            Lookup::Corrupt(e) | Lookup::BackendError(e) => {
+                self.counters.remote_misses.fetch_add(1, Ordering::Relaxed);
+                Err(e)
+            }
+        }
+    }
+
+    /// Like `cache::get_or_create_with`, with the local tier in front. A call that
+    /// didn't find the value in the backend counts as a remote miss, whether it ran
+    /// `create_fn` itself or waited on another caller's run.
+    pub async fn get_or_create<B, F, P>(
+        &self,
+        backend: &mut B,
+        key: &str,
+        on_error: OnError,
+        create_fn: F,
+    ) -> anyhow::Result<T>
+        where
+            B: CacheBackend + ?Sized,
+            F: Fn() -> P,
+            P: Future<Output=anyhow::Result<(T, usize)>>,
+    {
+        if let Some(value) = self.get_local(key) {
+            return Ok(value);
+        }
+        if let Lookup::Hit(value) = cache::lookup::<_, T>(backend, key, self.format).await {
+            self.counters.remote_hits.fetch_add(1, Ordering::Relaxed);
+            self.put_local(key, value.clone());
+            return Ok(value);
+        }
+        self.counters.remote_misses.fetch_add(1, Ordering::Relaxed);
+        let value = cache::get_or_create_with(backend, key, self.format, on_error, create_fn).await?;
+        self.put_local(key, value.clone());
+        Ok(value)
+    }
+
+    /// Write through to the backend and tell other instances to drop their copy.
+    pub async fn set<B>(&self, backend: &mut B, key: &str, value: T, seconds: usize) -> anyhow::Result<()>
+        where
+            B: CacheBackend + ?Sized,
+    {
+        cache::set_ex_with(backend, key, &value, seconds, self.format).await?;
+        self.put_local(key, value);
+        self.announce(backend, key).await;
+        Ok(())
+    }
+
+    /// Drop the local copy here and in every listening instance.
+    pub async fn invalidate<B>(&self, backend: &mut B, key: &str)
+        where
+            B: CacheBackend + ?Sized,
+    {
+        self.invalidate_local(key);
+        self.announce(backend, key).await;
+    }
+
+    pub fn invalidate_local(&self, key: &str) {
+        if self.local.lock().unwrap().entries.remove(key).is_some() {
+            self.counters.invalidations.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Drop local copies announced by other instances until the subscription closes.
+    pub async fn listen<S>(self: &Arc<Self>, subscriber: &S) -> anyhow::Result<JoinHandle<()>>
+        where
+            S: Subscriber + ?Sized,
+    {
+        let mut invalidations = cache::subscribe::<_, Invalidation>(subscriber, &self.channel, INVALIDATION_FORMAT).await?;
+        let near = Arc::downgrade(self);
+        Ok(tokio::spawn(async move {
+            while let Some(invalidation) = invalidations.recv().await {
+                let near = match near.upgrade() {
+                    Some(near) => near,
+                    None => break,
+                };
+                if invalidation.origin != near.origin {
+                    near.invalidate_local(&invalidation.key);
+                }
+            }
+        }))
+    }
+
+    pub fn len(&self) -> usize {
+        self.local.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn stats(&self) -> NearCacheStats {
+        let c = &self.counters;
+        NearCacheStats {
+            local: TierStats {
+                hits: c.local_hits.load(Ordering::Relaxed),
+                misses: c.local_misses.load(Ordering::Relaxed),
+            },
+            remote: TierStats {
+                hits: c.remote_hits.load(Ordering::Relaxed),
+                misses: c.remote_misses.load(Ordering::Relaxed),
+            },
+            evictions: c.evictions.load(Ordering::Relaxed),
+            invalidations: c.invalidations.load(Ordering::Relaxed),
+        }
+    }
+
+    fn get_local(&self, key: &str) -> Option<T> {
+        let value = self.local.lock().unwrap().get(key, Instant::now()).cloned();
+        let counter = match value {
+            Some(_) => &self.counters.local_hits,
+            None => &self.counters.local_misses,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
+    }
+
+    fn put_local(&self, key: &str, value: T) {
+        let expires = Instant::now() + self.ttl;
+        let evicted = self.local.lock().unwrap().insert(key.to_string(), value, expires, self.capacity);
+        self.counters.evictions.fetch_add(evicted, Ordering::Relaxed);
+    }
+
+    async fn announce<B>(&self, backend: &mut B, key: &str)
+        where
+            B: CacheBackend + ?Sized,
+    {
+        let invalidation = Invalidation {
+            origin: self.origin.clone(),
+            key: key.to_string(),
+        };
+        if let Err(e) = cache::publish(backend, &self.channel, &invalidation, INVALIDATION_FORMAT).await {
+            // other instances fall back to the local TTL
+            warn!("near cache invalidation {} err: {:?}", key, e);
+        }
+    }
+}
+
+fn instance_id() -> String {
+    let mut bytes = [0u8; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return format!("{}-{:p}", std::process::id(), &bytes);
+    }
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::backend::MemoryBackend;
+
+    fn near(capacity: usize, ttl: Duration) -> Arc<NearCache<u64>> {
+        Arc::new(NearCache::new(capacity, ttl, Format::default(), "near"))
+    }
+
+    #[tokio::test]
+    async fn test_tiers_and_ratios() {
+        let mut backend = MemoryBackend::new();
+        let near = near(10, Duration::from_secs(60));
+        cache::set_ex(&mut backend, "fee", &7u64, 60).await.unwrap();
+
+        assert_eq!(near.get(&mut backend, "fee").await.unwrap(), Some(7));
+        assert_eq!(near.get(&mut backend, "fee").await.unwrap(), Some(7));
+        assert_eq!(near.get(&mut backend, "fee").await.unwrap(), Some(7));
+        assert_eq!(near.get(&mut backend, "none").await.unwrap(), None);
+
+        let stats = near.stats();
+        assert_eq!(stats.local, TierStats { hits: 2, misses: 2 });
+        assert_eq!(stats.remote, TierStats { hits: 1, misses: 1 });
+        assert_eq!(stats.local.hit_ratio(), 0.5);
+
+        // served locally even after the remote copy is gone
+        backend.exec(vec![crate::helpers::backend::WriteOp::Del { key: "fee".into() }]).await.unwrap();
+        assert_eq!(near.get(&mut backend, "fee").await.unwrap(), Some(7));
+    }
+
+    #[tokio::test]
+    async fn test_get_or_create_counts_tiers() {
+        let mut backend = MemoryBackend::new();
+        let near = near(10, Duration::from_secs(60));
+        let create = || async { Ok((1u64, 60)) };
+
+        near.get_or_create(&mut backend, "k", OnError::Propagate, create).await.unwrap();
+        near.get_or_create(&mut backend, "k", OnError::Propagate, create).await.unwrap();
+        let other = NearCache::<u64>::new(10, Duration::from_secs(60), Format::default(), "near");
+        other.get_or_create(&mut backend, "k", OnError::Propagate, create).await.unwrap();
+
+        assert_eq!(near.stats().local, TierStats { hits: 1, misses: 1 });
+        assert_eq!(near.stats().remote, TierStats { hits: 0, misses: 1 });
+        assert_eq!(other.stats().remote, TierStats { hits: 1, misses: 0 });
+    }
+
+    #[tokio::test]
+    async fn test_single_flight_waiters_count_as_remote_misses() {
+        let backend = MemoryBackend::new();
+        let near = near(10, Duration::from_secs(60));
+        let callers = (0..3).map(|_| {
+            let mut backend = backend.clone();
+            let near = near.clone();
+            async move {
+                near.get_or_create(&mut backend, "flight", OnError::Propagate, || async {
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    Ok((1u64, 60))
+                }).await
+            }
+        });
+        for result in futures::future::join_all(callers).await {
+            assert_eq!(result.unwrap(), 1);
+        }
+        assert_eq!(near.stats().remote, TierStats { hits: 0, misses: 3 });
+    }
+
+    #[tokio::test]
+    async fn test_local_ttl_and_capacity() {
+        let mut backend = MemoryBackend::new();
+        let near = near(2, Duration::from_millis(30));
+        for (key, value) in [("a", 1u64), ("b", 2), ("c", 3)] {
+            near.set(&mut backend, key, value, 60).await.unwrap();
+        }
+        assert_eq!(near.len(), 2);
+        assert_eq!(near.stats().evictions, 1);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        // expired locally, refetched from the backend
+        assert_eq!(near.get(&mut backend, "c").await.unwrap(), Some(3));
+        assert_eq!(near.stats().remote.hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalidation_across_instances() {
+        let mut backend = MemoryBackend::new();
+        let first = near(10, Duration::from_secs(60));
+        let second = near(10, Duration::from_secs(60));
+        first.listen(&backend).await.unwrap();
+        second.listen(&backend).await.unwrap();
+
+        first.set(&mut backend, "tier", 1, 60).await.unwrap();
+        assert_eq!(second.get(&mut backend, "tier").await.unwrap(), Some(1));
+
+        first.set(&mut backend, "tier", 2, 60).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        // the writer keeps its fresh copy, the other instance refetches
+        assert_eq!(first.len(), 1);
+        assert_eq!(second.get(&mut backend, "tier").await.unwrap(), Some(2));
+        assert_eq!(second.stats().invalidations, 1);
+        assert_eq!(first.stats().invalidations, 0);
+    }
+}
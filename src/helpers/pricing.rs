@@ -0,0 +1,353 @@
+//! Cross rates: the price of any asset in a chosen quote, chained through pivot
+//! assets from `CoinSymbolCache` book tickers.
+//!
+//! Each leg converts one asset into the next at the top of its book: selling the
+//! base gets the bid, buying it pays the ask. A rate's bid is what one unit of the
+//! asset fetches in the quote, its ask what one unit costs.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+use rust_decimal::Decimal;
+
+use crate::conf::vars::OrderSide;
+use crate::helpers::asset_graph::Hop;
+use crate::helpers::coin_symbol::{BookTicker, CoinSymbolCache};
+use crate::helpers::instrument::InstrumentId;
+
+// assets a cross rate may go through by default
+pub const DEFAULT_PIVOTS: [&str; 6] = ["BTC", "BNB", "ETH", "USDT", "BUSD", "USDC"];
+
+/// Which paths `cross_rate` may use.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PricingOptions {
+    /// Assets allowed between the priced asset and the quote.
+    pub pivots: BTreeSet<String>,
+    pub max_hops: usize,
+    /// Smallest top-of-book depth, in the quote, for a path to count.
+    pub min_depth: Decimal,
+}
+
+impl Default for PricingOptions {
+    fn default() -> Self {
+        Self {
+            pivots: DEFAULT_PIVOTS.iter().map(|s| s.to_string()).collect(),
+            max_hops: 3,
+            min_depth: Decimal::ZERO,
+        }
+    }
+}
+
+/// One conversion of a cross rate and the book ticker it was priced from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Leg {
+    pub hop: Hop,
+    pub ticker: BookTicker,
+    pub age: Duration,
+}
+
+impl Leg {
+    /// Units of `hop.to` one unit of `hop.from` fetches.
+    pub fn bid(&self) -> Decimal {
+        match self.hop.side {
+            OrderSide::Sell => self.ticker.best_bid,
+            OrderSide::Buy => Decimal::ONE / self.ticker.best_ask,
+        }
+    }
+
+    /// Units of `hop.to` it costs to get one unit of `hop.from`.
+    pub fn ask(&self) -> Decimal {
+        match self.hop.side {
+            OrderSide::Sell => self.ticker.best_ask,
+            OrderSide::Buy => Decimal::ONE / self.ticker.best_bid,
+        }
+    }
+
+    pub fn mid(&self) -> Decimal {
+        let mid = (self.ticker.best_bid + self.ticker.best_ask) / Decimal::TWO;
+        match self.hop.side {
+            OrderSide::Sell => mid,
+            OrderSide::Buy => Decimal::ONE / mid,
+        }
+    }
+
+    /// Amount of `hop.from` the top of the book takes.
+    fn available(&self) -> Decimal {
+        match self.hop.side {
+            OrderSide::Sell => self.ticker.best_bid_qty,
+            OrderSide::Buy => self.ticker.best_ask_qty * self.ticker.best_ask,
+        }
+    }
+}
+
+/// The price of `asset` in `quote` and the legs it was chained from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CrossRate {
+    pub asset: String,
+    pub quote: String,
+    pub bid: Decimal,
+    pub ask: Decimal,
+    pub mid: Decimal,
+    pub legs: Vec<Leg>,
+    /// What the thinnest leg's top of book is worth, in the quote. Unbounded when
+    /// `asset` is `quote`.
+    pub depth: Decimal,
+}
+
+impl CrossRate {
+    fn identity(asset: &str) -> Self {
+        Self {
+            asset: asset.to_string(),
+            quote: asset.to_string(),
+            bid: Decimal::ONE,
+            ask: Decimal::ONE,
+            mid: Decimal::ONE,
+            legs: vec![],
+            depth: Decimal::MAX,
+        }
+    }
+
+    fn chain(asset: &str, quote: &str, legs: Vec<Leg>) -> Self {
+        let mut rate = Self::identity(asset);
+        rate.quote = quote.to_string();
+        // depth in units of `asset`, through the mid rates reached so far
+        let mut depth = Decimal::MAX;
+        for leg in &legs {
+            depth = depth.min(leg.available() / rate.mid);
+            rate.bid *= leg.bid();
+            rate.ask *= leg.ask();
+            rate.mid *= leg.mid();
+        }
+        rate.depth = depth * rate.mid;
+        rate.bid = rate.bid.normalize();
+        rate.ask = rate.ask.normalize();
+        rate.mid = rate.mid.normalize();
+        rate.legs = legs;
+        rate
+    }
+
+    /// Ask over bid, in basis points of the mid.
+    pub fn spread_bps(&self) -> Decimal {
+        (self.ask - self.bid) / self.mid * Decimal::from(10_000)
+    }
+
+    /// The instruments used, in order, e.g. `binance:spot:ETHBTC>binance:spot:BTCUSDT`.
+    pub fn route(&self) -> String {
+        self.legs.iter().map(|leg| leg.hop.id.to_string()).collect::<Vec<_>>().join(">")
+    }
+}
+
+impl fmt::Display for CrossRate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {} bid {} ask {} mid {}", self.asset, self.quote, self.bid, self.ask, self.mid)?;
+        if !self.legs.is_empty() {
+            write!(f, " via {}", self.route())?;
+        }
+        Ok(())
+    }
+}
+
+/// Why a leg couldn't be priced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rejection {
+    NoBookTicker,
+    Stale { age: Duration },
+    /// A zero or crossed top of book.
+    BadBook,
+}
+
+/// Why `cross_rate` found no price.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PricingError {
+    NoPath { asset: String, quote: String },
+    /// Paths exist, but each had a leg that couldn't be priced or was thinner than
+    /// `min_depth`.
+    NoUsablePath {
+        asset: String,
+        quote: String,
+        rejected: BTreeMap<InstrumentId, Rejection>,
+        thin: usize,
+    },
+}
+
+impl fmt::Display for PricingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PricingError::NoPath { asset, quote } => write!(f, "no path from {} to {}", asset, quote),
+            PricingError::NoUsablePath { asset, quote, rejected, thin } => {
+                write!(f, "no usable path from {} to {}: {} legs rejected, {} paths too thin", asset, quote, rejected.len(), thin)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PricingError {}
+
+/// Price `asset` in `quote` at `now` (unix millis) over the path with the tightest
+/// spread, the shorter one on a tie. Legs with a missing, stale or bad book ticker
+/// rule out every path through them.
+pub fn cross_rate(cache: &CoinSymbolCache, asset: &str, quote: &str, options: &PricingOptions, now: u64) -> Result<CrossRate, PricingError> {
+    if asset == quote {
+        return Ok(CrossRate::identity(asset));
+    }
+    let paths = cache.paths_via(asset, quote, options.max_hops, &options.pivots);
+    if paths.is_empty() {
+        return Err(PricingError::NoPath { asset: asset.to_string(), quote: quote.to_string() });
+    }
+
+    // instruments show up in many paths, look each one up once
+    let mut tickers: HashMap<InstrumentId, Result<(BookTicker, Duration), Rejection>> = HashMap::new();
+    let mut best: Option<CrossRate> = None;
+    let mut thin = 0;
+    'paths: for path in paths {
+        let mut legs = Vec::with_capacity(path.len());
+        for hop in path {
+            let ticker = tickers.entry(hop.id.clone()).or_insert_with(|| book_ticker(cache, &hop.id, now));
+            match ticker {
+                Ok((ticker, age)) => legs.push(Leg { hop, ticker: ticker.clone(), age: *age }),
+                Err(_) => continue 'paths,
+            }
+        }
+        let rate = CrossRate::chain(asset, quote, legs);
+        if rate.depth < options.min_depth {
+            thin += 1;
+            continue;
+        }
+        let better = best.as_ref().is_none_or(|best| {
+            (rate.spread_bps(), rate.legs.len()) < (best.spread_bps(), best.legs.len())
+        });
+        if better {
+            best = Some(rate);
+        }
+    }
+
+    best.ok_or_else(|| PricingError::NoUsablePath {
+        asset: asset.to_string(),
+        quote: quote.to_string(),
+        rejected: tickers.into_iter().filter_map(|(id, t)| t.err().map(|r| (id, r))).collect(),
+        thin,
+    })
+}
+
+fn book_ticker(cache: &CoinSymbolCache, id: &InstrumentId, now: u64) -> Result<(BookTicker, Duration), Rejection> {
+    let aged = cache.get_book_ticker_aged(id, now).ok_or(Rejection::NoBookTicker)?;
+    if aged.stale {
+        return Err(Rejection::Stale { age: aged.age });
+    }
+    let ticker = aged.value;
+    if ticker.best_bid.is_zero() || ticker.best_ask.is_zero() || ticker.best_bid > ticker.best_ask {
+        return Err(Rejection::BadBook);
+    }
+    Ok((ticker, aged.age))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use crate::conf::vars::{Market, Platform};
+    use crate::helpers::coin_symbol::now_millis;
+    use crate::helpers::instrument::Venue;
+    use crate::helpers::symbol_info::SymbolInfo;
+
+    const SPOT: Venue = Venue::new(Platform::Binance, Market::Spot);
+
+    fn d(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    fn list(cache: &CoinSymbolCache, base: &str, quote: &str, bid: &str, ask: &str, qty: &str) {
+        let symbol = format!("{}{}", base, quote);
+        cache.set_symbol_info(SPOT, SymbolInfo {
+            symbol: symbol.clone(),
+            base_asset: base.to_string(),
+            quote_asset: quote.to_string(),
+            status: "TRADING".to_string(),
+            ..Default::default()
+        });
+        cache.set_book_ticker(SPOT.instrument(symbol.as_str()), BookTicker {
+            update_id: 1,
+            symbol,
+            best_bid: d(bid),
+            best_bid_qty: d(qty),
+            best_ask: d(ask),
+            best_ask_qty: d(qty),
+        });
+    }
+
+    fn market() -> CoinSymbolCache {
+        let cache = CoinSymbolCache::new();
+        list(&cache, "BTC", "USDT", "30000", "30010", "2");
+        list(&cache, "ETH", "BTC", "0.05", "0.0501", "10");
+        // direct, but wide and thin
+        list(&cache, "ETH", "USDT", "1490", "1520", "0.01");
+        list(&cache, "USDC", "USDT", "0.9999", "1.0001", "100000");
+        list(&cache, "SOL", "USDC", "20", "20.02", "50");
+        cache
+    }
+
+    #[test]
+    fn test_picks_the_tightest_path() {
+        let cache = market();
+        let rate = cross_rate(&cache, "ETH", "USDT", &PricingOptions::default(), now_millis()).unwrap();
+        assert_eq!(rate.route(), "binance:spot:ETHBTC>binance:spot:BTCUSDT");
+        assert_eq!(rate.bid, d("1500"));
+        assert_eq!(rate.ask, d("1503.501"));
+        assert!(rate.spread_bps() < d("25"));
+        // 10 ETH on the ETHBTC bid is the thinner leg, 2 BTC would take 40
+        assert_eq!(rate.depth.round_dp(0), d("15018"));
+        assert_eq!(rate.legs[1].hop.side, OrderSide::Sell);
+
+        let rate = cross_rate(&cache, "SOL", "USDT", &PricingOptions::default(), now_millis()).unwrap();
+        assert_eq!(rate.to_string(), "SOL in USDT bid 19.998 ask 20.022002 mid 20.01 via binance:spot:SOLUSDC>binance:spot:USDCUSDT");
+    }
+
+    #[test]
+    fn test_buying_the_base_inverts_the_book() {
+        let cache = market();
+        let rate = cross_rate(&cache, "USDT", "BTC", &PricingOptions::default(), now_millis()).unwrap();
+        assert_eq!(rate.route(), "binance:spot:BTCUSDT");
+        assert_eq!(rate.legs[0].hop.side, OrderSide::Buy);
+        assert_eq!(rate.bid, Decimal::ONE / d("30010"));
+        assert_eq!(rate.ask, Decimal::ONE / d("30000"));
+        assert!(rate.bid < rate.mid && rate.mid < rate.ask);
+
+        let same = cross_rate(&cache, "USDT", "USDT", &PricingOptions::default(), now_millis()).unwrap();
+        assert_eq!((same.mid, same.route()), (Decimal::ONE, String::new()));
+    }
+
+    #[test]
+    fn test_refuses_stale_legs() {
+        let cache = market();
+        let btc = SPOT.instrument("BTCUSDT");
+        cache.book_tickers.get_mut(&btc).unwrap().received -= 120_000;
+
+        // only the direct market is left
+        let rate = cross_rate(&cache, "ETH", "USDT", &PricingOptions::default(), now_millis()).unwrap();
+        assert_eq!(rate.route(), "binance:spot:ETHUSDT");
+
+        let direct_only = PricingOptions { pivots: BTreeSet::new(), ..Default::default() };
+        match cross_rate(&cache, "BTC", "USDT", &direct_only, now_millis()).unwrap_err() {
+            PricingError::NoUsablePath { rejected, .. } => {
+                assert!(matches!(rejected[&btc], Rejection::Stale { age } if age >= Duration::from_secs(120)));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn test_pivots_depth_and_no_path() {
+        let cache = market();
+        let direct_only = PricingOptions { pivots: BTreeSet::new(), ..Default::default() };
+        let rate = cross_rate(&cache, "ETH", "USDT", &direct_only, now_millis()).unwrap();
+        assert_eq!(rate.route(), "binance:spot:ETHUSDT");
+
+        let deep = PricingOptions { min_depth: d("100"), ..direct_only };
+        let err = cross_rate(&cache, "ETH", "USDT", &deep, now_millis()).unwrap_err();
+        assert_eq!(err.to_string(), "no usable path from ETH to USDT: 0 legs rejected, 1 paths too thin");
+
+        let err = cross_rate(&cache, "DOGE", "USDT", &PricingOptions::default(), now_millis()).unwrap_err();
+        assert_eq!(err, PricingError::NoPath { asset: "DOGE".into(), quote: "USDT".into() });
+    }
+}
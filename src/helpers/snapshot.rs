@@ -0,0 +1,259 @@
+//! Warm-start snapshots of `CoinSymbolCache` in sled.
+//!
+//! The whole cache is written as one value, so a restore never mixes two snapshots.
+//! Restored entries are marked with the time they were saved and stay marked until
+//! the exchange refreshes them, see `CoinSymbolCache::restored_age`. They also keep
+//! their receive stamps, so a restored price is as stale as it really is.
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use tracing::{error, warn};
+
+use crate::helpers::codec::{self, Decoded, Format};
+use crate::helpers::coin_symbol::{now_millis, BookTicker, CoinSymbolCache, Dataset, PriceInfo, Stamped};
+use crate::helpers::instrument::InstrumentId;
+use crate::helpers::symbol_info::SymbolInfo;
+
+// sled tree and key holding the latest snapshot
+pub const SNAPSHOT_TREE: &str = "coin_symbol_cache";
+pub const SNAPSHOT_KEY: &str = "latest";
+// bump when `CacheSnapshot` changes; an outdated snapshot is ignored
+pub const SNAPSHOT_FORMAT: Format = Format::msgpack(2);
+
+/// Everything `CoinSymbolCache` holds, at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct CacheSnapshot {
+    /// Unix millis.
+    pub taken_at: u64,
+    pub coin_symbols: Vec<(String, Stamped<BTreeSet<InstrumentId>>)>,
+    pub symbols: Vec<(InstrumentId, Stamped<PriceInfo>)>,
+    pub symbol_infos: Vec<(InstrumentId, Stamped<SymbolInfo>)>,
+    pub book_tickers: Vec<(InstrumentId, Stamped<BookTicker>)>,
+}
+
+impl CacheSnapshot {
+    /// Copy the cache. Entries still restored from an older snapshot are copied too
+    /// and keep their original save time.
+    pub fn take(cache: &CoinSymbolCache, now: u64) -> Self {
+        Self {
+            taken_at: now,
+            coin_symbols: cache.coin_symbols.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+            symbols: cache.symbols.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+            symbol_infos: cache.symbol_infos.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+            book_tickers: cache.book_tickers.iter().map(|e| (e.key().clone(), e.value().clone())).collect(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.coin_symbols.len() + self.symbols.len() + self.symbol_infos.len() + self.book_tickers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fill the cache from the snapshot and mark every entry it provided as restored.
+    /// Entries the cache already has are newer and kept. Returns how many were restored.
+    pub fn restore(self, cache: &CoinSymbolCache) -> usize {
+        let taken_at = self.taken_at;
+        let mut restored = 0;
+        let mut mark = |dataset: Dataset, key: String, saved_at: u64| {
+            cache.restored.insert((dataset, key), saved_at);
+            restored += 1;
+        };
+
+        for (coin, ids) in self.coin_symbols {
+            if cache.coin_symbols.contains_key(&coin) {
+                continue;
+            }
+            ids.data.iter().for_each(|id| cache.index(id));
+            cache.coin_symbols.insert(coin.clone(), ids);
+            mark(Dataset::CoinSymbols, coin, taken_at);
+        }
+        for (id, info) in self.symbols {
+            if cache.symbols.contains_key(&id) {
+                continue;
+            }
+            cache.index(&id);
+            mark(Dataset::Symbols, id.to_string(), taken_at);
+            cache.symbols.insert(id, info);
+        }
+        for (id, info) in self.symbol_infos {
+            if cache.symbol_infos.contains_key(&id) {
+                continue;
+            }
+            cache.index(&id);
+            cache.link(&id, &info.data);
+            mark(Dataset::SymbolInfos, id.to_string(), taken_at);
+            cache.symbol_infos.insert(id, info);
+        }
+        for (id, ticker) in self.book_tickers {
+            if cache.book_tickers.contains_key(&id) {
+                continue;
+            }
+            cache.index(&id);
+            mark(Dataset::BookTickers, id.to_string(), taken_at);
+            cache.book_tickers.insert(id, ticker);
+        }
+        restored
+    }
+}
+
+/// Write a snapshot of the cache, replacing the previous one.
+pub fn save(db: &sled::Db, cache: &CoinSymbolCache) -> anyhow::Result<CacheSnapshot> {
+    let snapshot = CacheSnapshot::take(cache, now_millis());
+    let tree = db.open_tree(SNAPSHOT_TREE)?;
+    tree.insert(SNAPSHOT_KEY, codec::encode(SNAPSHOT_FORMAT, &snapshot)?)?;
+    tree.flush()?;
+    Ok(snapshot)
+}
+
+/// The last saved snapshot, `None` when there is none or it was written under
+/// another format version.
+pub fn load(db: &sled::Db) -> anyhow::Result<Option<CacheSnapshot>> {
+    let tree = db.open_tree(SNAPSHOT_TREE)?;
+    let bytes = match tree.get(SNAPSHOT_KEY)? {
+        Some(bytes) => bytes,
+        None => return Ok(None),
+    };
+    match codec::decode(SNAPSHOT_FORMAT, &bytes)? {
+        Decoded::Value(snapshot) => Ok(Some(snapshot)),
+        Decoded::Outdated(header, _) => {
+            warn!("ignore coin symbol cache snapshot written as {:?} v{}", header.codec, header.version);
+            Ok(None)
+        }
+        Decoded::Unrecognized => Err(anyhow!("load coin symbol cache snapshot err: no codec header")),
+    }
+}
+
+/// Restore the cache from the last snapshot, if any. Returns the snapshot's age and
+/// how many entries it restored.
+pub fn restore(db: &sled::Db, cache: &CoinSymbolCache) -> anyhow::Result<Option<(Duration, usize)>> {
+    Ok(load(db)?.map(|snapshot| {
+        let age = Duration::from_millis(now_millis().saturating_sub(snapshot.taken_at));
+        (age, snapshot.restore(cache))
+    }))
+}
+
+/// Save a snapshot every `interval` until the task is dropped.
+pub fn spawn(db: &'static sled::Db, cache: &'static CoinSymbolCache, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        // the first tick fires at once, before there is anything worth saving
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            if let Err(e) = save(db, cache) {
+                error!("snapshot coin symbol cache err: {:?}", e);
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use crate::conf::vars::{Market, Platform};
+    use crate::helpers::instrument::Venue;
+
+    const SPOT: Venue = Venue::new(Platform::Binance, Market::Spot);
+
+    fn temporary() -> sled::Db {
+        sled::Config::new().temporary(true).open().unwrap()
+    }
+
+    fn filled() -> CoinSymbolCache {
+        let cache = CoinSymbolCache::new();
+        cache.add_coin_symbol("BTC", SPOT.instrument("BTCUSDT"));
+        cache.set_symbols(SPOT.instrument("BTCUSDT"), PriceInfo {
+            base_asset: "BTC".to_string(),
+            quote_asset: "USDT".to_string(),
+            price: Decimal::new(30000, 0),
+            updated: 1,
+        });
+        cache.set_symbol_info(SPOT, SymbolInfo {
+            symbol: "BTCUSDT".to_string(),
+            status: "TRADING".to_string(),
+            tick_size: Decimal::new(1, 2),
+            ..Default::default()
+        });
+        cache.set_book_ticker(SPOT.instrument("BTCUSDT"), BookTicker {
+            update_id: 9,
+            symbol: "BTCUSDT".to_string(),
+            best_bid: Decimal::new(29999, 0),
+            best_bid_qty: Decimal::ONE,
+            best_ask: Decimal::new(30001, 0),
+            best_ask_qty: Decimal::ONE,
+        });
+        cache
+    }
+
+    #[test]
+    fn test_save_and_restore() {
+        let db = temporary();
+        assert!(load(&db).unwrap().is_none());
+
+        let saved = save(&db, &filled()).unwrap();
+        assert_eq!(saved.len(), 4);
+        assert_eq!(load(&db).unwrap().unwrap(), saved);
+
+        let cache = CoinSymbolCache::new();
+        let (_, restored) = restore(&db, &cache).unwrap().unwrap();
+        assert_eq!(restored, 4);
+        let btc = SPOT.instrument("BTCUSDT");
+        assert_eq!(cache.get_symbols(&btc).unwrap().price, Decimal::new(30000, 0));
+        assert_eq!(cache.get_symbol_info(&btc).unwrap().tick_size, Decimal::new(1, 2));
+        assert_eq!(cache.get_book_ticker(&btc).unwrap().update_id, 9);
+        assert_eq!(cache.get_coin_symbols("BTC").unwrap(), BTreeSet::from([btc.clone()]));
+        assert_eq!(cache.venues_of("BTCUSDT"), vec![SPOT]);
+        assert!(cache.is_degraded());
+    }
+
+    #[test]
+    fn test_restored_entries_age_until_refreshed() {
+        let mut snapshot = CacheSnapshot::take(&filled(), 1_000);
+        snapshot.coin_symbols.clear();
+        let cache = CoinSymbolCache::new();
+        assert_eq!(snapshot.restore(&cache), 3);
+
+        let btc = SPOT.instrument("BTCUSDT");
+        assert_eq!(cache.restored_age(Dataset::Symbols, &btc, 61_000), Some(Duration::from_secs(60)));
+        assert!(cache.is_restored(Dataset::BookTickers, &btc));
+        assert_eq!(cache.restored_counts().get(&Dataset::SymbolInfos), Some(&1));
+
+        cache.set_symbols(btc.clone(), PriceInfo::default());
+        assert_eq!(cache.restored_age(Dataset::Symbols, &btc, 61_000), None);
+        assert!(cache.is_restored(Dataset::BookTickers, &btc));
+
+        cache.set_symbol_info(SPOT, SymbolInfo { symbol: "BTCUSDT".to_string(), ..Default::default() });
+        cache.set_book_ticker(btc.clone(), BookTicker { update_id: 10, ..cache.get_book_ticker(&btc).unwrap() });
+        assert!(!cache.is_degraded());
+    }
+
+    #[test]
+    fn test_restore_keeps_newer_entries() {
+        let snapshot = CacheSnapshot::take(&filled(), 1_000);
+        let cache = CoinSymbolCache::new();
+        let btc = SPOT.instrument("BTCUSDT");
+        cache.set_symbols(btc.clone(), PriceInfo { price: Decimal::new(31000, 0), ..Default::default() });
+
+        assert_eq!(snapshot.restore(&cache), 3);
+        assert_eq!(cache.get_symbols(&btc).unwrap().price, Decimal::new(31000, 0));
+        assert!(!cache.is_restored(Dataset::Symbols, &btc));
+    }
+
+    #[test]
+    fn test_outdated_snapshot_is_ignored() {
+        let db = temporary();
+        let tree = db.open_tree(SNAPSHOT_TREE).unwrap();
+        tree.insert(SNAPSHOT_KEY, codec::encode(Format::msgpack(1), &CacheSnapshot::default()).unwrap()).unwrap();
+        assert!(load(&db).unwrap().is_none());
+
+        tree.insert(SNAPSHOT_KEY, b"garbage".to_vec()).unwrap();
+        assert!(load(&db).is_err());
+    }
+}
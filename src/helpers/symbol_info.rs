@@ -0,0 +1,253 @@
+use std::fmt;
+use std::str::FromStr;
+
+use binance::rest_model::{Filters, Symbol, SymbolPermission};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+// binance symbol status of a market open for orders
+pub const STATUS_TRADING: &str = "TRADING";
+
+/// Which way to move a value that isn't on a valid increment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Round {
+    Down,
+    Up,
+    Nearest,
+}
+
+/// Trading rules of one symbol, from exchange info.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub struct SymbolInfo {
+    pub symbol: String,
+    pub base_asset: String,
+    pub quote_asset: String,
+    /// e.g. `TRADING`, `BREAK`, `HALT`.
+    pub status: String,
+    pub base_precision: u32,
+    pub quote_precision: u32,
+    /// PRICE_FILTER; a zero bound or tick means the exchange doesn't enforce it.
+    pub min_price: Decimal,
+    pub max_price: Decimal,
+    pub tick_size: Decimal,
+    /// LOT_SIZE
+    pub min_qty: Decimal,
+    pub max_qty: Decimal,
+    pub step_size: Decimal,
+    /// MIN_NOTIONAL, zero when the symbol has none.
+    pub min_notional: Decimal,
+    /// e.g. `SPOT`, `MARGIN`.
+    pub permissions: Vec<String>,
+}
+
+/// Why an order wouldn't be accepted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderCheckError {
+    NotTrading { symbol: String, status: String },
+    PriceOutOfRange { price: Decimal, min: Decimal, max: Decimal },
+    PriceOffTick { price: Decimal, tick: Decimal },
+    QtyOutOfRange { qty: Decimal, min: Decimal, max: Decimal },
+    QtyOffStep { qty: Decimal, step: Decimal },
+    BelowMinNotional { notional: Decimal, min: Decimal },
+}
+
+impl fmt::Display for OrderCheckError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderCheckError::NotTrading { symbol, status } => write!(f, "{} is {}, not trading", symbol, status),
+            OrderCheckError::PriceOutOfRange { price, min, max } => write!(f, "price {} outside [{}, {}]", price, min, max),
+            OrderCheckError::PriceOffTick { price, tick } => write!(f, "price {} not a multiple of tick {}", price, tick),
+            OrderCheckError::QtyOutOfRange { qty, min, max } => write!(f, "qty {} outside [{}, {}]", qty, min, max),
+            OrderCheckError::QtyOffStep { qty, step } => write!(f, "qty {} not a multiple of step {}", qty, step),
+            OrderCheckError::BelowMinNotional { notional, min } => write!(f, "notional {} below minimum {}", notional, min),
+        }
+    }
+}
+
+impl std::error::Error for OrderCheckError {}
+
+impl SymbolInfo {
+    pub fn is_trading(&self) -> bool {
+        self.status == STATUS_TRADING
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.iter().any(|p| p.eq_ignore_ascii_case(permission))
+    }
+
+    /// Move `price` onto the tick grid.
+    pub fn round_price(&self, price: Decimal, round: Round) -> Decimal {
+        round_to(price, self.tick_size, round)
+    }
+
+    /// Move `qty` onto the lot step grid.
+    pub fn round_qty(&self, qty: Decimal, round: Round) -> Decimal {
+        round_to(qty, self.step_size, round)
+    }
+
+    pub fn meets_min_notional(&self, price: Decimal, qty: Decimal) -> bool {
+        price * qty >= self.min_notional
+    }
+
+    /// Check a limit order against status, PRICE_FILTER, LOT_SIZE and MIN_NOTIONAL.
+    pub fn check_order(&self, price: Decimal, qty: Decimal) -> Result<(), OrderCheckError> {
+        if !self.is_trading() {
+            return Err(OrderCheckError::NotTrading {
+                symbol: self.symbol.clone(),
+                status: self.status.clone(),
+            });
+        }
+        if !in_range(price, self.min_price, self.max_price) {
+            return Err(OrderCheckError::PriceOutOfRange { price, min: self.min_price, max: self.max_price });
+        }
+        if !on_grid(price, self.tick_size) {
+            return Err(OrderCheckError::PriceOffTick { price, tick: self.tick_size });
+        }
+        if !in_range(qty, self.min_qty, self.max_qty) {
+            return Err(OrderCheckError::QtyOutOfRange { qty, min: self.min_qty, max: self.max_qty });
+        }
+        if !on_grid(qty, self.step_size) {
+            return Err(OrderCheckError::QtyOffStep { qty, step: self.step_size });
+        }
+        if !self.meets_min_notional(price, qty) {
+            return Err(OrderCheckError::BelowMinNotional { notional: price * qty, min: self.min_notional });
+        }
+        Ok(())
+    }
+}
+
+impl From<&Symbol> for SymbolInfo {
+    fn from(symbol: &Symbol) -> Self {
+        let mut info = SymbolInfo {
+            symbol: symbol.symbol.clone(),
+            base_asset: symbol.base_asset.clone(),
+            quote_asset: symbol.quote_asset.clone(),
+            status: symbol.status.clone(),
+            base_precision: symbol.base_asset_precision as u32,
+            quote_precision: symbol.quote_asset_precision as u32,
+            permissions: symbol
+                .permissions
+                .iter()
+                .map(|p| match p {
+                    SymbolPermission::Spot => "SPOT",
+                    SymbolPermission::Margin => "MARGIN",
+                    SymbolPermission::Other => "OTHER",
+                }.to_string())
+                .collect(),
+            ..Default::default()
+        };
+        for filter in &symbol.filters {
+            match filter {
+                Filters::PriceFilter { min_price, max_price, tick_size } => {
+                    info.min_price = decimal(*min_price);
+                    info.max_price = decimal(*max_price);
+                    info.tick_size = decimal(*tick_size);
+                }
+                Filters::LotSize { min_qty, max_qty, step_size } => {
+                    info.min_qty = decimal(*min_qty);
+                    info.max_qty = decimal(*max_qty);
+                    info.step_size = decimal(*step_size);
+                }
+                Filters::MinNotional { min_notional, .. } => info.min_notional = decimal(*min_notional),
+                _ => {}
+            }
+        }
+        info
+    }
+}
+
+/// Exchange info filters arrive as f64; go through the shortest decimal string so
+/// 0.01 becomes exactly 0.01.
+fn decimal(f: f64) -> Decimal {
+    Decimal::from_str(&f.to_string()).unwrap_or_default().normalize()
+}
+
+fn round_to(value: Decimal, increment: Decimal, round: Round) -> Decimal {
+    if increment.is_zero() {
+        return value;
+    }
+    let steps = value / increment;
+    let steps = match round {
+        Round::Down => steps.floor(),
+        Round::Up => steps.ceil(),
+        Round::Nearest => steps.round(),
+    };
+    (steps * increment).normalize()
+}
+
+fn in_range(value: Decimal, min: Decimal, max: Decimal) -> bool {
+    value >= min && (max.is_zero() || value <= max)
+}
+
+fn on_grid(value: Decimal, increment: Decimal) -> bool {
+    increment.is_zero() || (value % increment).is_zero()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn btcusdt() -> SymbolInfo {
+        let json = r#"{
+            "symbol": "BTCUSDT", "status": "TRADING", "baseAsset": "BTC", "baseAssetPrecision": 8,
+            "quoteAsset": "USDT", "quotePrecision": 8, "quoteAssetPrecision": 8,
+            "baseCommissionPrecision": 8, "quoteCommissionPrecision": 8,
+            "orderTypes": ["LIMIT", "MARKET"], "icebergAllowed": true, "ocoAllowed": true,
+            "quoteOrderQtyMarketAllowed": true, "isSpotTradingAllowed": true, "isMarginTradingAllowed": true,
+            "filters": [
+                {"filterType": "PRICE_FILTER", "minPrice": "0.01000000", "maxPrice": "1000000.00000000", "tickSize": "0.01000000"},
+                {"filterType": "LOT_SIZE", "minQty": "0.00001000", "maxQty": "9000.00000000", "stepSize": "0.00001000"},
+                {"filterType": "MIN_NOTIONAL", "minNotional": "10.00000000", "applyToMarket": true, "avgPriceMins": 5}
+            ],
+            "permissions": ["SPOT", "MARGIN"]
+        }"#;
+        let symbol: Symbol = serde_json::from_str(json).unwrap();
+        SymbolInfo::from(&symbol)
+    }
+
+    fn d(s: &str) -> Decimal {
+        Decimal::from_str(s).unwrap()
+    }
+
+    #[test]
+    fn test_from_exchange_info() {
+        let info = btcusdt();
+        assert!(info.is_trading());
+        assert!(info.has_permission("margin"));
+        assert_eq!(info.tick_size, d("0.01"));
+        assert_eq!(info.step_size, d("0.00001"));
+        assert_eq!(info.min_notional, d("10"));
+        assert_eq!(info.max_qty, d("9000"));
+    }
+
+    #[test]
+    fn test_rounding() {
+        let info = btcusdt();
+        assert_eq!(info.round_price(d("30000.129"), Round::Down), d("30000.12"));
+        assert_eq!(info.round_price(d("30000.121"), Round::Up), d("30000.13"));
+        assert_eq!(info.round_price(d("30000.125"), Round::Nearest), d("30000.12"));
+        assert_eq!(info.round_price(d("30000.12"), Round::Up), d("30000.12"));
+        assert_eq!(info.round_qty(d("0.123456789"), Round::Down), d("0.12345"));
+
+        let unfiltered = SymbolInfo::default();
+        assert_eq!(unfiltered.round_qty(d("1.23"), Round::Down), d("1.23"));
+    }
+
+    #[test]
+    fn test_check_order() {
+        let mut info = btcusdt();
+        assert_eq!(info.check_order(d("30000.12"), d("0.001")), Ok(()));
+        assert!(info.meets_min_notional(d("10000"), d("0.001")));
+        assert!(!info.meets_min_notional(d("9999.99"), d("0.001")));
+        assert!(matches!(info.check_order(d("30000.125"), d("0.001")), Err(OrderCheckError::PriceOffTick { .. })));
+        assert!(matches!(info.check_order(d("30000"), d("0.000015")), Err(OrderCheckError::QtyOffStep { .. })));
+        assert!(matches!(info.check_order(d("30000"), d("10000")), Err(OrderCheckError::QtyOutOfRange { .. })));
+        assert_eq!(
+            info.check_order(d("30000"), d("0.0003")).unwrap_err().to_string(),
+            "notional 9.0000 below minimum 10"
+        );
+
+        info.status = "BREAK".to_string();
+        assert_eq!(info.check_order(d("30000"), d("1")).unwrap_err().to_string(), "BTCUSDT is BREAK, not trading");
+    }
+}
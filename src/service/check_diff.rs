@@ -1,34 +1,64 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
 use std::str::FromStr;
 use std::sync::atomic::AtomicBool;
-use binance::ws_model::{WebsocketEvent, WebsocketEventUntag};
+use std::sync::Arc;
+use std::time::Duration;
+use binance::ws_model::{DayTickerEvent, WebsocketEvent, WebsocketEventUntag};
 use rust_decimal::Decimal;
 use tokio::select;
 use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::watch;
 use binance::api::*;
 use binance::general::General;
+use binance::rest_model::{ExchangeInformation, Symbol};
 use binance::websockets::*;
 use chrono::Local;
-use redis::{AsyncCommands, RedisResult};
+use redis::aio::MultiplexedConnection;
+use redis::AsyncCommands;
 use rust_decimal::prelude::FromPrimitive;
 use tracing::{error, info, warn};
-use crate::{conf, db};
-use crate::helpers::coin_symbol::{PriceInfo, BookTicker};
+use crate::db;
+use crate::conf::config::{Conf, StrategyConfig};
+use crate::conf::vars::{Market, Platform};
+use crate::helpers::coin_symbol::{self, CoinSymbolCache, Dataset, PriceInfo, BookTicker, SequenceStats, StaleAction, SymbolDiff};
+use crate::helpers::cache::{self, OnError, Pipeline};
+use crate::helpers::codec::Format;
+use crate::helpers::instrument::{InstrumentId, Venue};
+use crate::helpers::keys::{self, KeyKind};
+use crate::helpers::near_cache::NearCache;
+use crate::helpers::symbol_info::SymbolInfo;
 
-const THREADS: i64 = 10;
+// channel announcing `SymbolDiff`s, under the configured key namespace
+pub const SYMBOL_DIFF_CHANNEL: &str = "coin_symbols";
+const SYMBOL_DIFF_FORMAT: Format = Format::json(2);
+// channel invalidating cached exchange info, nothing announces on it yet
+const EXCHANGE_INFO_CHANNEL: &str = "exchange_info";
+// bincode can't read the internally tagged symbol filters
+const EXCHANGE_INFO_FORMAT: Format = Format::msgpack(1);
+// how often book ticker rejections and gaps are logged
+const SEQUENCE_REPORT_SECS: u64 = 60;
+// the only venue this service follows for now
+pub const BINANCE_SPOT: Venue = Venue::new(Platform::Binance, Market::Spot);
 
 #[derive(Debug, Clone)]
 pub struct CheckDiff {
     pub senders: HashMap<i64, UnboundedSender<WebsocketEvent>>,
+    /// Live config. Spread thresholds and loop intervals are re-read from it, see
+    /// `conf::watch::reconcile` for the strategy keys that need a restart.
+    pub conf: watch::Receiver<Arc<Conf>>,
+    /// Exchange info shared by the refresh loops and, through Redis, by every instance.
+    pub exchange_info: Arc<NearCache<ExchangeInformation>>,
 }
 
 impl CheckDiff {
-    pub fn new() -> Self {
+    pub fn new(conf: watch::Receiver<Arc<Conf>>) -> Self {
+        let startup = conf.borrow().clone();
         let mut txs = HashMap::new();
-        for i in 0..THREADS {
+        for i in 0..startup.strategy.workers as i64 {
             let (tx, mut rx) = mpsc::unbounded_channel::<WebsocketEvent>();
             txs.insert(i, tx.clone());
 
+            let conf = conf.clone();
             tokio::spawn(async move {
                 let cache = db::get_async_coin_symbols_cache().unwrap();
                 loop {
@@ -36,16 +66,9 @@ impl CheckDiff {
                         event = rx.recv() => {
                             // println!("{:?}", event);
                             if let Some(WebsocketEvent::DayTicker(tick_event)) = event {
-                                if let Ok(option_price_info) = cache.get_symbols(&tick_event.symbol) {
-                                    if let Some(price_info) = option_price_info {
-                                        let key = format!("{}{}", conf::vars::EX_PREFIX, &price_info.base_asset);
-                                        if let Ok(option_symbols) = cache.get_coin_symbols(&key) {
-                                            if let Some(symbols) = option_symbols {
-                                                // println!("-----xxxx------xxxxx: {:?}, {:?}", cache.symbols.len(), cache.coin_symbols.len());
-                                                info!("{:?}, {:?}, {:?}", tick_event.symbol, price_info, symbols);
-                                            }
-                                        }
-                                    }
+                                if let Some(price_info) = cache.get_symbols(&BINANCE_SPOT.instrument(tick_event.symbol.as_str())) {
+                                    let live = conf.borrow().clone();
+                                    check_spread(&live.strategy, cache, &tick_event, &price_info);
                                 }
                             }
                         }
@@ -54,8 +77,16 @@ impl CheckDiff {
             });
         }
 
+        let exchange_info = Arc::new(NearCache::new(
+            1,
+            Duration::from_secs(startup.strategy.symbols_refresh_secs),
+            EXCHANGE_INFO_FORMAT,
+            keys::channel(EXCHANGE_INFO_CHANNEL),
+        ));
         CheckDiff {
             senders: txs,
+            conf,
+            exchange_info,
         }
     }
 
@@ -64,32 +95,53 @@ impl CheckDiff {
         let client: General = Binance::new(None, None);
         let mut redis = db::get_redis_connection().await?;
         let cache = db::database::get_async_coin_symbols_cache().unwrap();
-        if let Ok(exchange_info) = client.exchange_info().await {
-            for symbol in exchange_info.symbols {
-                let key = format!("{}{}", conf::vars::EX_PREFIX, &symbol.base_asset);
-                let _: RedisResult<bool> = redis.hset(&key, &symbol.symbol, "1".to_string()).await;
-                let _ = cache.set_coin_symbols(key, symbol.symbol);
+        let near = self.exchange_info.clone();
+        let expiry = self.conf.borrow().strategy.symbols_refresh_secs as usize;
+        if let Ok(exchange_info) = exchange_info(&near, &mut redis, &client, expiry).await {
+            let (snapshot, quotes) = coin_symbols_snapshot(&exchange_info.symbols);
+            let diffs = cache.sync_coin_symbols(BINANCE_SPOT, snapshot.clone());
+            forget_delisted(cache, &diffs);
+
+            // rewrite the mirror, dropping symbols and whole coins delisted while we were down
+            let mut gone: BTreeSet<String> = diffs.iter().map(|diff| diff.coin.clone()).collect();
+            match mirrored_coins(&mut redis).await {
+                Ok(coins) => gone.extend(coins),
+                Err(e) => error!("scan coin symbols mirror err: {:?}", e),
+            }
+            gone.retain(|coin| !snapshot.contains_key(coin));
+            let mut pipe = Pipeline::new();
+            for coin in &gone {
+                pipe.del(keys::coin_symbols(coin));
+            }
+            for (coin, symbols) in &snapshot {
+                let key = keys::coin_symbols(coin);
+                pipe.del(&key)
+                    .hset_many(key, symbols.iter().map(|s| (BINANCE_SPOT.instrument(s.as_str()).to_string(), quotes[s].as_str())))?;
+            }
+            if !gone.is_empty() {
+                info!("drop {} delisted coins from the coin symbols mirror", gone.len());
+            }
+            if let Err(e) = pipe.execute(&mut redis).await {
+                error!("mirror coin symbols to redis err: {:?}", e);
             }
         }
         warn!("init coin symbols {:?}", Local::now().timestamp_millis());
 
+        let conf = self.conf.clone();
         tokio::spawn(async move {
             loop {
+                let refresh = tokio::time::Duration::from_secs(conf.borrow().strategy.coin_symbols_refresh_secs);
                 select! {
-                    _oks = tokio::time::sleep(tokio::time::Duration::from_secs(300)) => {
-                        if let Ok(exchange_info) = client.exchange_info().await {
-                            for symbol in exchange_info.symbols {
-                                let key = format!("{}{}", conf::vars::EX_PREFIX, &symbol.base_asset);
-                                if let Ok(option_coin_symbols) = cache.get_coin_symbols(&key) {
-                                    if let Some(coin_symbols) = option_coin_symbols {
-                                        if coin_symbols.len() > 0 {
-                                            continue
-                                        }
-                                    }
-                                }
-                                let _ = cache.set_coin_symbols(&key, symbol.symbol);
+                    _oks = tokio::time::sleep(refresh) => {
+                        if let Ok(exchange_info) = exchange_info(&near, &mut redis, &client, expiry).await {
+                            let (snapshot, quotes) = coin_symbols_snapshot(&exchange_info.symbols);
+                            let diffs = cache.sync_coin_symbols(BINANCE_SPOT, snapshot);
+                            forget_delisted(cache, &diffs);
+                            if let Err(e) = publish_symbol_diffs(&mut redis, &diffs, &quotes).await {
+                                error!("publish coin symbol diffs err: {:?}", e);
                             }
                         }
+                        info!("exchange info cache {}", near.stats());
                     }
                 }
             }
@@ -101,38 +153,48 @@ impl CheckDiff {
     #[allow(dead_code)]
     pub async fn init_symbols(&self) -> anyhow::Result<()> {
         let client: General = Binance::new(None, None);
+        let mut redis = db::get_redis_connection().await?;
         let cache = db::database::get_async_coin_symbols_cache().unwrap();
-        if let Ok(exchange_info) = client.exchange_info().await {
+        let near = self.exchange_info.clone();
+        let expiry = self.conf.borrow().strategy.symbols_refresh_secs as usize;
+        if let Ok(exchange_info) = exchange_info(&near, &mut redis, &client, expiry).await {
             for symbol in exchange_info.symbols {
-                cache.set_symbols(&symbol.symbol, PriceInfo {
+                update_symbol_info(cache, &symbol);
+                cache.set_symbols(BINANCE_SPOT.instrument(symbol.symbol.as_str()), PriceInfo {
                     base_asset: symbol.base_asset,
                     quote_asset: symbol.quote_asset,
                     price: Decimal::ZERO,
                     updated: 0,
-                })?;
+                });
             }
         }
-        warn!("init symbols {:?}", Local::now().timestamp_millis());
+        warn!("init symbols {:?}, not trading: {:?}", Local::now().timestamp_millis(), cache.non_trading_symbols());
+        warn!("asset graph: {} assets, {} markets", cache.graph().assets().len(), cache.graph().len());
+        if cache.is_degraded() {
+            warn!("still serving restored entries: {:?}", cache.restored_counts());
+        }
 
+        // pinned by `reconcile`, it's also the exchange info cache ttl
+        let refresh = tokio::time::Duration::from_secs(expiry as u64);
         tokio::spawn(async move {
             loop {
                 select! {
-                    _oks = tokio::time::sleep(tokio::time::Duration::from_secs(3)) => {
-                        if let Ok(exchange_info) = client.exchange_info().await {
+                    _oks = tokio::time::sleep(refresh) => {
+                        if let Ok(exchange_info) = exchange_info(&near, &mut redis, &client, expiry).await {
                             for symbol in exchange_info.symbols {
-
-                                if let Ok(_info) = cache.get_symbols(&symbol.symbol) {
+                                // rules and status can change at any time
+                                update_symbol_info(cache, &symbol);
+                                // keep the last price of symbols we already track, add new listings
+                                if cache.get_symbols(&BINANCE_SPOT.instrument(symbol.symbol.as_str())).is_some() {
                                     continue;
                                 }
-
-                                if let Err(e) = cache.set_symbols(&symbol.symbol, PriceInfo {
+                                info!("new symbol {}", symbol.symbol);
+                                cache.set_symbols(BINANCE_SPOT.instrument(symbol.symbol.as_str()), PriceInfo {
                                     base_asset: symbol.base_asset,
                                     quote_asset: symbol.quote_asset,
                                     price: Decimal::ZERO,
                                     updated: 0,
-                                }) {
-                                    error!("insert symbols error: {:?}", e);
-                                }
+                                });
                             }
                         }
                     }
@@ -143,8 +205,36 @@ impl CheckDiff {
         Ok(())
     }
 
+    /// Sweep the cache for stale entries every `stale_sweep_secs` and report them,
+    /// evicting them when `evict_stale` is set.
+    pub async fn sweep_stale(&self) -> anyhow::Result<()> {
+        let cache = db::get_async_coin_symbols_cache().unwrap();
+        let conf = self.conf.clone();
+        tokio::spawn(async move {
+            loop {
+                let (interval, action) = {
+                    let strategy = &conf.borrow().strategy;
+                    let action = if strategy.evict_stale { StaleAction::Evict } else { StaleAction::Flag };
+                    (tokio::time::Duration::from_secs(strategy.stale_sweep_secs), action)
+                };
+                select! {
+                    _oks = tokio::time::sleep(interval) => {
+                        let report = cache.sweep(coin_symbol::now_millis(), action);
+                        if !report.is_empty() {
+                            warn!("stale cache entries: {}", report);
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    #[allow(clippy::result_large_err)]
     pub async fn last_price(&self, close_tx: UnboundedSender<bool>) -> anyhow::Result<()> {
         let txs = self.senders.clone();
+        let workers = txs.len() as i64;
 
         tokio::spawn(async move {
             let keep_running = AtomicBool::new(true);
@@ -156,7 +246,7 @@ impl CheckDiff {
                 for tick_events in events {
                     if let WebsocketEvent::DayTicker(tick_event) = tick_events.clone() {
                         // println!("{:?}", tick_event.clone());
-                        let sharding = &tick_event.last_trade_id % THREADS;
+                        let sharding = tick_event.last_trade_id % workers;
                         if let Some(tx) = txs.get(&sharding) {
                             if let Err(e) = tx.send(tick_events.clone()) {
                                 error!("send tick events to channel error: {:?}", e);
@@ -164,17 +254,14 @@ impl CheckDiff {
                             }
                         }
 
-                        if let Ok(option_price_info) = cache.get_symbols(&tick_event.symbol) {
-                            if let Some(price_info) = option_price_info {
-                                if let Err(e) = cache.set_symbols(&tick_event.symbol, PriceInfo {
-                                    base_asset: price_info.base_asset,
-                                    quote_asset: price_info.quote_asset,
-                                    price: Decimal::from_str(tick_event.current_close.as_str()).unwrap_or_default(),
-                                    updated: tick_event.event_time,
-                                }) {
-                                    error!("last price insert symbols error: {:?}", e);
-                                }
-                            }
+                        let id = BINANCE_SPOT.instrument(tick_event.symbol.as_str());
+                        if let Some(price_info) = cache.get_symbols(&id) {
+                            cache.set_symbols(id, PriceInfo {
+                                base_asset: price_info.base_asset,
+                                quote_asset: price_info.quote_asset,
+                                price: Decimal::from_str(tick_event.current_close.as_str()).unwrap_or_default(),
+                                updated: tick_event.event_time,
+                            });
                         }
                     }
                 }
@@ -199,14 +286,24 @@ impl CheckDiff {
         Ok(())
     }
 
+    #[allow(clippy::result_large_err)]
     pub async fn book_ticker(&self, close_tx: UnboundedSender<bool>) -> anyhow::Result<()> {
+        tokio::spawn(async move {
+            let cache = db::get_async_coin_symbols_cache().unwrap();
+            let mut reported = HashMap::new();
+            loop {
+                tokio::time::sleep(tokio::time::Duration::from_secs(SEQUENCE_REPORT_SECS)).await;
+                report_sequences(cache, &mut reported);
+            }
+        });
+
         tokio::spawn(async move {
             let keep_running = AtomicBool::new(true);
             let all_book_ticker = all_book_ticker_stream();
             let cache = db::get_async_coin_symbols_cache().unwrap();
             let mut web_socket: WebSockets<'_, WebsocketEventUntag> = WebSockets::new(|events: WebsocketEventUntag| {
                 if let WebsocketEventUntag::BookTicker(tick_event) = events {
-                    let _ = cache.set_book_ticker(&tick_event.symbol, BookTicker {
+                    cache.set_book_ticker(BINANCE_SPOT.instrument(tick_event.symbol.as_str()), BookTicker {
                         update_id: tick_event.update_id,
                         symbol: tick_event.symbol.clone(),
                         best_bid: Decimal::from_f64(tick_event.best_bid).unwrap_or_default(),
@@ -220,7 +317,7 @@ impl CheckDiff {
                 Ok(())
             });
 
-            web_socket.connect(&all_book_ticker).await.unwrap(); // check error
+            web_socket.connect(all_book_ticker).await.unwrap(); // check error
             if let Err(e) = web_socket.event_loop(&keep_running).await {
                 error!("book_ticker connect Error: {:?}", e);
                 close_tx.send(true).unwrap();
@@ -230,4 +327,198 @@ impl CheckDiff {
         });
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Log book ticker rejections and gaps that appeared since the last report.
+fn report_sequences(cache: &CoinSymbolCache, reported: &mut HashMap<InstrumentId, SequenceStats>) {
+    let mut changed: Vec<(InstrumentId, SequenceStats, SequenceStats)> = cache
+        .sequence_stats()
+        .into_iter()
+        .filter_map(|(id, stats)| {
+            let before = reported.insert(id.clone(), stats).unwrap_or_default();
+            (before != stats).then_some((id, before, stats))
+        })
+        .collect();
+    if changed.is_empty() {
+        return;
+    }
+    let rejected: u64 = changed.iter().map(|(_, before, now)| now.rejected - before.rejected).sum();
+    if rejected > 0 {
+        warn!("book ticker rejected {} out-of-order updates, {} in total", rejected, cache.rejected_book_tickers());
+    }
+    // ids aren't contiguous, so some gaps are normal; show the symbols that skipped most
+    changed.retain(|(_, before, now)| now.gaps > before.gaps);
+    changed.sort_by_key(|(_, before, now)| std::cmp::Reverse(now.skipped - before.skipped));
+    let worst: Vec<String> = changed
+        .iter()
+        .take(5)
+        .map(|(id, before, now)| format!(
+            "{} {} gaps/{} ids, largest {}, last {:?}",
+            id,
+            now.gaps - before.gaps,
+            now.skipped - before.skipped,
+            now.largest_gap,
+            now.last_gap,
+        ))
+        .collect();
+    if !worst.is_empty() {
+        info!("book ticker gaps in {} instruments, worst: {}", changed.len(), worst.join("; "));
+    }
+}
+
+/// Store the symbol's trading rules and log when its status changes.
+fn update_symbol_info(cache: &CoinSymbolCache, symbol: &Symbol) {
+    let info = SymbolInfo::from(symbol);
+    let trading = info.is_trading();
+    match cache.set_symbol_info(BINANCE_SPOT, info) {
+        Some(previous) if previous.status != symbol.status => {
+            warn!("symbol {} status {} -> {}", symbol.symbol, previous.status, symbol.status)
+        }
+        None if !trading => warn!("symbol {} is {}, not trading", symbol.symbol, symbol.status),
+        _ => {}
+    }
+}
+
+/// Coin to symbols, and each symbol's quote asset, from an exchange info snapshot.
+fn coin_symbols_snapshot(symbols: &[Symbol]) -> (HashMap<String, BTreeSet<String>>, HashMap<String, String>) {
+    let mut snapshot: HashMap<String, BTreeSet<String>> = HashMap::new();
+    let mut quotes = HashMap::new();
+    for symbol in symbols {
+        snapshot.entry(symbol.base_asset.clone()).or_default().insert(symbol.symbol.clone());
+        quotes.insert(symbol.symbol.clone(), symbol.quote_asset.clone());
+    }
+    (snapshot, quotes)
+}
+
+/// Drop delisted instruments from the cache and its asset graph.
+fn forget_delisted(cache: &CoinSymbolCache, diffs: &[SymbolDiff]) {
+    for diff in diffs {
+        for symbol in &diff.removed {
+            cache.remove_symbol(&diff.venue.instrument(symbol.as_str()));
+        }
+    }
+}
+
+/// Exchange info from the local copy, then Redis, then Binance. The copy is at most
+/// `expiry` seconds old, the same as the refresh interval, so a refresh loop sees
+/// listing changes at most one interval later than it would reading Binance itself.
+async fn exchange_info(
+    near: &NearCache<ExchangeInformation>,
+    redis: &mut MultiplexedConnection,
+    client: &General,
+    expiry: usize,
+) -> anyhow::Result<ExchangeInformation> {
+    let key = keys::exchange_info(BINANCE_SPOT.platform, BINANCE_SPOT.market);
+    let result = near.get_or_create(redis, &key, OnError::Bypass, || async {
+        Ok((client.exchange_info().await?, expiry))
+    }).await;
+    if let Err(e) = &result {
+        error!("exchange info err: {:?}", e);
+    }
+    result
+}
+
+/// Coins with a `coin_symbols` hash in Redis.
+async fn mirrored_coins(redis: &mut MultiplexedConnection) -> anyhow::Result<BTreeSet<String>> {
+    let keyspace = keys::keyspace();
+    let mut iter = redis.scan_match::<_, String>(keyspace.pattern(KeyKind::CoinSymbols)).await?;
+    let mut coins = BTreeSet::new();
+    while let Some(key) = iter.next_item().await {
+        if let Some((KeyKind::CoinSymbols, mut parts)) = keyspace.parse(&key) {
+            coins.extend(parts.pop());
+        }
+    }
+    Ok(coins)
+}
+
+/// Log each listing change, apply it to the Redis mirror and announce it on the
+/// `coin_symbols` channel.
+async fn publish_symbol_diffs(redis: &mut MultiplexedConnection, diffs: &[SymbolDiff], quotes: &HashMap<String, String>) -> anyhow::Result<()> {
+    if diffs.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = Pipeline::new();
+    for diff in diffs {
+        info!("coin symbols changed: {}", diff);
+        let key = keys::coin_symbols(&diff.coin);
+        pipe.hset_many(&key, diff.added.iter().map(|s| (diff.venue.instrument(s.as_str()).to_string(), quotes[s].as_str())))?
+            .hdel(key, diff.removed.iter().map(|s| diff.venue.instrument(s.as_str()).to_string()));
+    }
+    pipe.execute(redis).await?;
+
+    let channel = keys::channel(SYMBOL_DIFF_CHANNEL);
+    for diff in diffs {
+        cache::publish(redis, &channel, diff, SYMBOL_DIFF_FORMAT).await?;
+    }
+    Ok(())
+}
+
+/// Compare the ticking market with the same base quoted in the other configured quote
+/// assets, on any venue, and report spreads of at least `min_spread_bps`.
+fn check_spread(strategy: &StrategyConfig, cache: &CoinSymbolCache, tick_event: &DayTickerEvent, price_info: &PriceInfo) {
+    if !strategy.is_quote(&price_info.quote_asset) || !strategy.is_watched(&price_info.base_asset) {
+        return;
+    }
+    let volume = Decimal::from_str(&tick_event.quote_volume).unwrap_or_default();
+    if volume < strategy.min_volume_24h {
+        return;
+    }
+    let price = Decimal::from_str(&tick_event.current_close).unwrap_or_default();
+
+    let ticking = BINANCE_SPOT.instrument(tick_event.symbol.as_str());
+    let now = coin_symbol::now_millis();
+    if let Some(ids) = cache.get_coin_symbols(&price_info.base_asset) {
+        // halted or delisting markets, and prices restored from a snapshot, are stale
+        for id in ids.iter().filter(|id| **id != ticking && cache.is_trading(id) && !cache.is_restored(Dataset::Symbols, *id)) {
+            // a market that stopped ticking would report a phantom spread
+            if let Some(other) = cache.get_symbols_aged(id, now).and_then(|aged| aged.fresh()) {
+                if !strategy.is_quote(&other.quote_asset) {
+                    continue;
+                }
+                if let Some(bps) = spread_bps(price, other.price) {
+                    if bps.abs() >= strategy.min_spread_bps {
+                        info!("spread {} {}bps: {} {} vs {} {}", price_info.base_asset, bps.round_dp(2),
+                            ticking, price, id, other.price);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Spread of `price` over `other` in basis points, `None` when either price is unknown.
+fn spread_bps(price: Decimal, other: Decimal) -> Option<Decimal> {
+    if price.is_zero() || other.is_zero() {
+        return None;
+    }
+    Some((price - other) / other * Decimal::from(10_000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::codec::{self, Decoded};
+
+    #[test]
+    fn test_exchange_info_round_trips() {
+        let json = r#"{
+            "timezone": "UTC", "serverTime": 1, "rateLimits": [], "exchangeFilters": [],
+            "symbols": [{
+                "symbol": "BTCUSDT", "status": "TRADING", "baseAsset": "BTC", "baseAssetPrecision": 8,
+                "quoteAsset": "USDT", "quotePrecision": 8, "quoteAssetPrecision": 8,
+                "baseCommissionPrecision": 8, "quoteCommissionPrecision": 8,
+                "orderTypes": ["LIMIT"], "icebergAllowed": true, "ocoAllowed": true,
+                "quoteOrderQtyMarketAllowed": true, "isSpotTradingAllowed": true, "isMarginTradingAllowed": false,
+                "filters": [{"filterType": "PRICE_FILTER", "minPrice": "0.01", "maxPrice": "1000000", "tickSize": "0.01"}],
+                "permissions": ["SPOT"]
+            }]
+        }"#;
+        let info: ExchangeInformation = serde_json::from_str(json).unwrap();
+        let bytes = codec::encode(EXCHANGE_INFO_FORMAT, &info).unwrap();
+        let decoded: ExchangeInformation = match codec::decode(EXCHANGE_INFO_FORMAT, &bytes).unwrap() {
+            Decoded::Value(info) => info,
+            _ => panic!("exchange info didn't decode"),
+        };
+        assert_eq!(SymbolInfo::from(&decoded.symbols[0]), SymbolInfo::from(&info.symbols[0]));
+    }
+}